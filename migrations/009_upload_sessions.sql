@@ -0,0 +1,9 @@
+CREATE TABLE upload_sessions (
+	id serial primary key unique,
+	user_id bigint not null references users on delete cascade,
+	data text not null,
+	filepaths text[] not null,
+	hashes text[] not null,
+	completed int not null default 0,
+	time timestamp not null
+);
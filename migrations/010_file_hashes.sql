@@ -0,0 +1,2 @@
+ALTER TABLE posts ADD file_hashes text[] NOT NULL default '{}';
+ALTER TABLE posts ADD file_sizes bigint[] NOT NULL default '{}';
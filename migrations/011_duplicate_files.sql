@@ -0,0 +1,2 @@
+ALTER TABLE reports ALTER COLUMN user_id DROP NOT NULL;
+CREATE INDEX posts_file_hashes ON posts USING GIN (file_hashes);
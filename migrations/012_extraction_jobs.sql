@@ -0,0 +1,8 @@
+CREATE TABLE extraction_jobs (
+	post_id int primary key unique references posts on delete cascade,
+	status int not null default 0,
+	attempts int not null default 0,
+	error text,
+	run_at timestamp not null,
+	updated timestamp not null
+);
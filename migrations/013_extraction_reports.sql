@@ -0,0 +1 @@
+ALTER TABLE extraction_jobs ADD report text;
@@ -0,0 +1,2 @@
+ALTER TABLE posts ADD draft bool not null default false;
+ALTER TABLE posts ADD publish_at timestamp;
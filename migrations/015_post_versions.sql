@@ -0,0 +1,14 @@
+CREATE TABLE post_versions (
+	post_id int not null references posts on delete cascade,
+	version int not null,
+	changelog text,
+	files text[] not null,
+	local_files text[] not null,
+	file_hashes text[] not null default '{}',
+	file_sizes bigint[] not null default '{}',
+	time timestamp not null,
+	primary key (post_id, version)
+);
+
+INSERT INTO post_versions (post_id, version, files, local_files, file_hashes, file_sizes, time)
+SELECT id, 1, files, local_files, file_hashes, file_sizes, time FROM posts;
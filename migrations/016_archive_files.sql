@@ -0,0 +1,9 @@
+CREATE TABLE archive_files (
+	post_id int not null references posts on delete cascade,
+	archive text not null,
+	path text not null,
+	size bigint not null,
+	compressed_size bigint
+);
+
+CREATE INDEX archive_files_post_id ON archive_files (post_id);
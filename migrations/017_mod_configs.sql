@@ -0,0 +1,12 @@
+CREATE TABLE mod_configs (
+	post_id int not null references posts on delete cascade,
+	path text not null,
+	name text,
+	author text,
+	version text,
+	description text,
+	dll text[] not null default '{}',
+	enabled bool not null default true
+);
+
+CREATE INDEX mod_configs_post_id ON mod_configs (post_id);
@@ -0,0 +1,14 @@
+CREATE TABLE post_dlls (
+	post_id int not null references posts on delete cascade,
+	path text not null,
+	name text not null,
+	sha256 text not null,
+	file_version text,
+	product_version text,
+	product_name text,
+	company_name text,
+	file_description text
+);
+
+CREATE INDEX post_dlls_post_id ON post_dlls (post_id);
+CREATE INDEX post_dlls_sha256 ON post_dlls (sha256);
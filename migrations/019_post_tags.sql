@@ -0,0 +1,7 @@
+CREATE TABLE post_tags (
+	post_id int not null references posts on delete cascade,
+	tag text not null,
+	primary key (post_id, tag)
+);
+
+CREATE INDEX post_tags_tag ON post_tags (tag);
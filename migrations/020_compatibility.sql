@@ -0,0 +1,3 @@
+ALTER TABLE posts ADD games int[];
+ALTER TABLE posts ADD inferred_games int[] not null default '{}';
+ALTER TABLE posts ADD min_dml_version text;
@@ -0,0 +1,9 @@
+CREATE TABLE download_events (
+	post_id int not null references posts on delete cascade,
+	variant int not null,
+	time timestamp not null,
+	fingerprint text not null
+);
+
+CREATE INDEX download_events_post_id_time ON download_events (post_id, time);
+CREATE INDEX download_events_time ON download_events (time);
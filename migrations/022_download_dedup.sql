@@ -0,0 +1,2 @@
+ALTER TABLE download_events ADD counted bool not null default true;
+CREATE INDEX download_events_post_id_fingerprint_time ON download_events (post_id, fingerprint, time);
@@ -0,0 +1 @@
+ALTER TABLE posts ADD indexed_weekly_downloads bigint not null default 0;
@@ -0,0 +1,9 @@
+CREATE TABLE post_changelogs (
+	id serial primary key,
+	post_id int not null references posts on delete cascade,
+	version int not null,
+	text text not null,
+	time timestamp not null
+);
+
+CREATE INDEX post_changelogs_post_id ON post_changelogs (post_id);
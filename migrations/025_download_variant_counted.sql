@@ -0,0 +1,2 @@
+ALTER TABLE download_events ADD variant_counted bool not null default true;
+UPDATE download_events SET variant_counted = counted;
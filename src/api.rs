@@ -1,8 +1,10 @@
 use crate::AppState;
+use admin::*;
 use axum::{routing::*, Router};
 use ids::*;
 use posts::*;
 
+pub mod admin;
 pub mod ids;
 pub mod posts;
 
@@ -10,10 +12,18 @@ pub fn route(state: AppState) -> Router {
 	Router::new()
 		.route("/api/v1/posts", get(search_posts))
 		.route("/api/v1/posts/count", get(count_posts))
+		.route("/api/v1/posts/tags", get(search_tags))
 		.route("/api/v1/posts/:id", get(get_post).delete(delete_post))
 		.route("/api/v1/posts/posts", get(get_multiple_posts))
 		.route("/api/v1/posts/edit", post(edit))
 		.route("/api/v1/posts/upload_image", get(upload_image))
+		.route(
+			"/api/v1/images/upload",
+			post(store_image).layer(axum::extract::DefaultBodyLimit::max(
+				crate::images::MAX_IMAGE_SIZE + 1024 * 1024,
+			)),
+		)
+		.route("/images/:id/:variant", get(get_image))
 		.route("/api/v1/posts/upload", get(upload_ws))
 		.route("/api/v1/posts/:id/download/:variant", get(download))
 		.route("/api/v1/posts/:id/like", post(like))
@@ -21,6 +31,17 @@ pub fn route(state: AppState) -> Router {
 		.route("/api/v1/posts/:id/author", post(add_author))
 		.route("/api/v1/posts/:id/dependency", post(add_dependency))
 		.route("/api/v1/posts/:id/report", post(report))
+		.route("/api/v1/posts/:id/extraction", get(get_extraction_status))
+		.route("/api/v1/posts/:id/publish", post(publish))
+		.route("/api/v1/posts/:id/versions", get(get_post_versions))
+		.route("/api/v1/posts/:id/changelog", get(get_changelog))
+		.route("/api/v1/posts/:id/downloads", get(get_download_history))
+		.route("/api/v1/posts/:id/files", get(get_archive_files))
+		.route("/api/v1/dlls", get(find_dll))
+		.route(
+			"/api/v1/posts/:id/versions/:version/download/:variant",
+			get(download_version),
+		)
 		.route(
 			"/api/v1/posts/:post/comment/:comment",
 			delete(delete_comment),
@@ -35,6 +56,11 @@ pub fn route(state: AppState) -> Router {
 			"/api/v1/reserve",
 			post(create_reservation).delete(delete_reservation),
 		)
+		.route("/api/v1/admin/reports/:id", post(handle_report))
+		.route(
+			"/api/v1/admin/extraction",
+			get(extraction_progress).post(reextract),
+		)
 		.layer(tower_http::cors::CorsLayer::permissive())
 		.with_state(state)
 }
@@ -0,0 +1,71 @@
+use crate::jobs::*;
+use crate::models::*;
+use crate::AppState;
+use axum::{extract::*, http::StatusCode};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct ReextractRequest {
+	/// Only this post, combined with post_type if both are set
+	pub post_id: Option<i32>,
+	pub post_type: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReextractResponse {
+	pub queued: Vec<i32>,
+}
+
+/// Queues posts for extraction again, the worker clears their old PVs, modules and customize items before each one
+pub async fn reextract(
+	user: User,
+	State(state): State<AppState>,
+	Json(request): Json<ReextractRequest>,
+) -> Result<Json<ReextractResponse>, StatusCode> {
+	if !user.is_admin(&state.config) {
+		return Err(StatusCode::UNAUTHORIZED);
+	}
+
+	let queued = enqueue_extractions(request.post_id, request.post_type, &state.db).await;
+	if queued.is_empty() && request.post_id.is_some() {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	Ok(Json(ReextractResponse { queued }))
+}
+
+/// Marks a report as dealt with, which drops it from the admin page and the report count
+pub async fn handle_report(
+	Path(id): Path<i32>,
+	user: User,
+	State(state): State<AppState>,
+) -> Result<(), StatusCode> {
+	if !user.is_admin(&state.config) {
+		return Err(StatusCode::UNAUTHORIZED);
+	}
+
+	let result = sqlx::query!(
+		"UPDATE reports SET admin_handled = $2 WHERE id = $1",
+		id,
+		user.id
+	)
+	.execute(&state.db)
+	.await
+	.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	if result.rows_affected() == 0 {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	Ok(())
+}
+
+pub async fn extraction_progress(
+	user: User,
+	State(state): State<AppState>,
+) -> Result<Json<ExtractionProgress>, StatusCode> {
+	if !user.is_admin(&state.config) {
+		return Err(StatusCode::UNAUTHORIZED);
+	}
+
+	Ok(Json(ExtractionProgress::get(&state.db).await))
+}
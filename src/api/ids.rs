@@ -1,19 +1,166 @@
+use crate::api::posts::{check_raw_filter, quote_filter};
 use crate::models::*;
 use crate::AppState;
 use axum::{extract::*, http::StatusCode, response::*};
 use base64::prelude::*;
 use itertools::*;
 use serde::{Deserialize, Serialize};
+use sha2::Digest;
 use std::collections::*;
 use std::path::Path;
-use tokio::process::Command;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct SearchParams {
 	pub query: Option<String>,
+	/// Raw Meilisearch filter, only accepted from admins
 	pub filter: Option<String>,
 	pub limit: Option<usize>,
 	pub offset: Option<usize>,
+	/// Post the entries come from, -1 for the base game
+	pub post: Option<i32>,
+	pub min_id: Option<i32>,
+	pub max_id: Option<i32>,
+	/// Three letter character code, like MIK or LUK
+	pub chara: Option<String>,
+}
+
+impl SearchParams {
+	fn meilisearch_filter(&self, id_attribute: &str) -> Result<String, (StatusCode, String)> {
+		let mut filters = Vec::new();
+		if let Some(filter) = &self.filter {
+			filters.push(format!("({filter})"));
+		}
+		if let Some(post) = self.post {
+			filters.push(format!("post_id = {post}"));
+		}
+		if let Some(min_id) = self.min_id {
+			filters.push(format!("{id_attribute} >= {min_id}"));
+		}
+		if let Some(max_id) = self.max_id {
+			filters.push(format!("{id_attribute} <= {max_id}"));
+		}
+		if let Some(chara) = &self.chara {
+			validate_chara(chara)?;
+			filters.push(format!("chara = {}", quote_filter(chara)));
+		}
+		Ok(filters.join(" AND "))
+	}
+}
+
+fn validate_chara(chara: &str) -> Result<(), (StatusCode, String)> {
+	if chara.len() != 3 || !chara.chars().all(|c| c.is_ascii_uppercase()) {
+		return Err((StatusCode::BAD_REQUEST, format!("Invalid chara {chara}")));
+	}
+	Ok(())
+}
+
+/// One of the five chart slots MM+ shows on song select
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChartSlot {
+	Easy,
+	Normal,
+	Hard,
+	Extreme,
+	ExtraExtreme,
+}
+
+impl ChartSlot {
+	/// Meilisearch attribute holding the slot's star count
+	fn attribute(&self) -> &'static str {
+		match self {
+			Self::Easy => "stars.easy",
+			Self::Normal => "stars.normal",
+			Self::Hard => "stars.hard",
+			Self::Extreme => "stars.extreme",
+			Self::ExtraExtreme => "stars.extra_extreme",
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+	#[default]
+	Asc,
+	Desc,
+}
+
+impl std::fmt::Display for SortOrder {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Self::Asc => "asc",
+			Self::Desc => "desc",
+		})
+	}
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct PvSearchParams {
+	pub query: Option<String>,
+	pub filter: Option<String>,
+	pub limit: Option<usize>,
+	pub offset: Option<usize>,
+	/// Post the songs come from, -1 for the base game
+	pub post: Option<i32>,
+	pub min_id: Option<i32>,
+	pub max_id: Option<i32>,
+	/// Only songs where every one of these characters performs
+	#[serde(default)]
+	pub chara: Vec<String>,
+	pub min_bpm: Option<i32>,
+	pub max_bpm: Option<i32>,
+	/// Only songs that have a chart in every one of these slots
+	#[serde(default)]
+	pub has: Vec<ChartSlot>,
+	/// Slot that min_level, max_level and level_order apply to
+	pub level: Option<ChartSlot>,
+	pub min_level: Option<f32>,
+	pub max_level: Option<f32>,
+	pub level_order: Option<SortOrder>,
+}
+
+impl PvSearchParams {
+	fn meilisearch_filter(&self) -> Result<String, (StatusCode, String)> {
+		let mut filters = Vec::new();
+		if let Some(filter) = &self.filter {
+			filters.push(format!("({filter})"));
+		}
+		if let Some(post) = self.post {
+			filters.push(format!("post = {post}"));
+		}
+		if let Some(min_id) = self.min_id {
+			filters.push(format!("pv_id >= {min_id}"));
+		}
+		if let Some(max_id) = self.max_id {
+			filters.push(format!("pv_id <= {max_id}"));
+		}
+		for chara in &self.chara {
+			validate_chara(chara)?;
+			filters.push(format!("performers.chara = {}", quote_filter(chara)));
+		}
+		if let Some(min_bpm) = self.min_bpm {
+			filters.push(format!("bpm >= {min_bpm}"));
+		}
+		if let Some(max_bpm) = self.max_bpm {
+			filters.push(format!("bpm <= {max_bpm}"));
+		}
+		for slot in &self.has {
+			filters.push(format!("{} EXISTS", slot.attribute()));
+		}
+		if let Some(level) = self.level {
+			for (bound, operator) in [(self.min_level, ">="), (self.max_level, "<=")] {
+				let Some(bound) = bound else {
+					continue;
+				};
+				if !bound.is_finite() {
+					return Err((StatusCode::BAD_REQUEST, format!("Invalid level {bound}")));
+				}
+				filters.push(format!("{} {operator} {bound}", level.attribute()));
+			}
+		}
+		Ok(filters.join(" AND "))
+	}
 }
 
 #[derive(Serialize, Deserialize)]
@@ -26,6 +173,267 @@ pub struct MeilisearchPv {
 	pub song_info: Option<pv_db::SongInfo>,
 	pub song_info_en: Option<pv_db::SongInfo>,
 	pub levels: [Option<pv_db::Level>; 5],
+	/// Every chart regardless of edition, levels only keeps the ones MM+ shows
+	#[serde(default)]
+	pub charts: Vec<PvChart>,
+	#[serde(default)]
+	pub performers: Vec<PvPerformer>,
+	#[serde(default)]
+	pub bpm: Option<i32>,
+	#[serde(default)]
+	pub song_file_name: Option<String>,
+	#[serde(default)]
+	pub sabi: Option<PvSabi>,
+	/// Release date as YYYYMMDD
+	#[serde(default)]
+	pub date: Option<i32>,
+	#[serde(default)]
+	pub stars: PvStars,
+}
+
+/// Numeric star counts of the charts in each slot, missing when the song has no chart there
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct PvStars {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub easy: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub normal: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub hard: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub extreme: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub extra_extreme: Option<f32>,
+}
+
+impl PvStars {
+	fn from_charts(charts: &[PvChart]) -> Self {
+		let mut stars = Self::default();
+		for chart in charts {
+			let slot = match (chart.difficulty, chart.edition) {
+				(PvDifficulty::Easy, 0) => &mut stars.easy,
+				(PvDifficulty::Normal, 0) => &mut stars.normal,
+				(PvDifficulty::Hard, 0) => &mut stars.hard,
+				(PvDifficulty::Extreme, 0) => &mut stars.extreme,
+				(PvDifficulty::Extreme, 1) => &mut stars.extra_extreme,
+				_ => continue,
+			};
+			*slot = chart.level;
+		}
+		stars
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PvPerformer {
+	/// Three letter character code, like MIK or LUK
+	pub chara: String,
+	#[serde(rename = "type")]
+	pub performer_type: Option<String>,
+	/// Module the performer wears in the PV
+	pub module: Option<i32>,
+}
+
+impl PvPerformer {
+	pub fn chara_name(&self) -> &str {
+		match self.chara.as_str() {
+			"MIK" => "Miku",
+			"RIN" => "Rin",
+			"LEN" => "Len",
+			"LUK" => "Luka",
+			"NER" => "Neru",
+			"HAK" => "Haku",
+			"KAI" => "Kaito",
+			"MEI" => "Meiko",
+			"SAK" => "Sakine",
+			"TET" => "Teto",
+			"EXT" => "Extra",
+			chara => chara,
+		}
+	}
+}
+
+/// The chorus part played on the song select screen, in seconds
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PvSabi {
+	pub start_time: f32,
+	pub play_time: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum PvDifficulty {
+	Easy,
+	Normal,
+	Hard,
+	Extreme,
+	Encore,
+}
+
+impl PvDifficulty {
+	fn from_key(key: &str) -> Option<Self> {
+		match key {
+			"easy" => Some(Self::Easy),
+			"normal" => Some(Self::Normal),
+			"hard" => Some(Self::Hard),
+			"extreme" => Some(Self::Extreme),
+			"encore" => Some(Self::Encore),
+			_ => None,
+		}
+	}
+}
+
+impl std::fmt::Display for PvDifficulty {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Self::Easy => "Easy",
+			Self::Normal => "Normal",
+			Self::Hard => "Hard",
+			Self::Extreme => "Extreme",
+			Self::Encore => "Encore",
+		})
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PvChart {
+	pub difficulty: PvDifficulty,
+	pub edition: i32,
+	/// Star count, PV_LV_07_5 is 7.5
+	pub level: Option<f32>,
+	pub script_file_name: Option<String>,
+	/// Attributes set to 1, like original, extra or slide
+	pub attributes: Vec<String>,
+}
+
+impl PvChart {
+	/// Edition 1 is the Extra version of a difficulty, anything past that only mods use
+	pub fn name(&self) -> String {
+		match self.edition {
+			0 => self.difficulty.to_string(),
+			1 => format!("Extra {}", self.difficulty),
+			edition => format!("{} (edition {edition})", self.difficulty),
+		}
+	}
+
+	/// CSS variable the chart is coloured with
+	pub fn color(&self) -> &'static str {
+		match (self.difficulty, self.edition) {
+			(PvDifficulty::Easy, _) => "--diva-easy",
+			(PvDifficulty::Normal, _) => "--diva-normal",
+			(PvDifficulty::Hard, _) => "--diva-hard",
+			(PvDifficulty::Extreme, 0) => "--diva-extreme",
+			_ => "--diva-exex",
+		}
+	}
+}
+
+/// Converts a PV_LV_XX_Y level into a star count
+fn parse_level(level: &str) -> Option<f32> {
+	let (whole, half) = level.strip_prefix("PV_LV_")?.split_once('_')?;
+	let whole = whole.parse::<u8>().ok()?;
+	let half = half.parse::<u8>().ok()?;
+	Some(whole as f32 + half as f32 / 10.0)
+}
+
+/// Raw `key=value` lines of a pv_db grouped by PV id, keys have their `pv_XXX.` prefix removed
+fn pv_db_values(data: &str) -> BTreeMap<i32, BTreeMap<String, String>> {
+	let mut pvs: BTreeMap<i32, BTreeMap<String, String>> = BTreeMap::new();
+	for line in data.lines() {
+		let line = line.trim();
+		if line.starts_with('#') {
+			continue;
+		}
+		let Some((key, value)) = line.split_once('=') else {
+			continue;
+		};
+		let Some((pv, key)) = key.split_once('.') else {
+			continue;
+		};
+		let Some(Ok(id)) = pv.strip_prefix("pv_").map(|id| id.parse::<i32>()) else {
+			continue;
+		};
+		pvs.entry(id)
+			.or_default()
+			.insert(String::from(key), String::from(value));
+	}
+	pvs
+}
+
+/// Every `performer.<index>` entry of a PV, in slot order
+fn pv_performers(values: &BTreeMap<String, String>) -> Vec<PvPerformer> {
+	let mut performers: BTreeMap<usize, PvPerformer> = BTreeMap::new();
+	for (key, value) in values {
+		let parts = key.split('.').collect::<Vec<_>>();
+		if parts.len() != 3 || parts[0] != "performer" {
+			continue;
+		}
+		// Skips performer.num
+		let Ok(index) = parts[1].parse::<usize>() else {
+			continue;
+		};
+		let performer = performers.entry(index).or_insert(PvPerformer {
+			chara: String::new(),
+			performer_type: None,
+			module: None,
+		});
+		match parts[2] {
+			"chara" => performer.chara = value.clone(),
+			"type" => performer.performer_type = Some(value.clone()),
+			"pv_costume" => performer.module = value.parse().ok(),
+			_ => {}
+		}
+	}
+
+	performers
+		.into_values()
+		.filter(|performer| !performer.chara.is_empty())
+		.collect()
+}
+
+fn pv_sabi(values: &BTreeMap<String, String>) -> Option<PvSabi> {
+	Some(PvSabi {
+		start_time: values.get("sabi.start_time")?.parse().ok()?,
+		play_time: values.get("sabi.play_time")?.parse().ok()?,
+	})
+}
+
+/// Every `difficulty.<name>.<index>` entry of a PV
+fn pv_charts(values: &BTreeMap<String, String>) -> Vec<PvChart> {
+	let mut charts: BTreeMap<(PvDifficulty, usize), PvChart> = BTreeMap::new();
+	for (key, value) in values {
+		let parts = key.split('.').collect::<Vec<_>>();
+		if parts.len() < 4 || parts[0] != "difficulty" {
+			continue;
+		}
+		let Some(difficulty) = PvDifficulty::from_key(parts[1]) else {
+			continue;
+		};
+		// Skips difficulty.<name>.length
+		let Ok(index) = parts[2].parse::<usize>() else {
+			continue;
+		};
+		let chart = charts.entry((difficulty, index)).or_insert(PvChart {
+			difficulty,
+			edition: 0,
+			level: None,
+			script_file_name: None,
+			attributes: Vec::new(),
+		});
+		match (parts[3], parts.get(4)) {
+			("edition", None) => chart.edition = value.parse().unwrap_or(0),
+			("level", None) => chart.level = parse_level(value),
+			("script_file_name", None) => chart.script_file_name = Some(value.clone()),
+			("attribute", Some(attribute)) if value == "1" => {
+				chart.attributes.push(String::from(*attribute))
+			}
+			_ => {}
+		}
+	}
+
+	let mut charts = charts.into_values().collect::<Vec<_>>();
+	charts.sort_by_key(|chart| (chart.difficulty, chart.edition));
+	charts
 }
 
 #[derive(Serialize, Deserialize)]
@@ -46,9 +454,66 @@ pub struct MeilisearchCstmItem {
 	pub customize_item: module_db::CustomizeItem,
 }
 
+/// DivaModLoader accepts `dll` as either a single path or a list
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+	One(String),
+	Many(Vec<String>),
+}
+
 #[derive(Serialize, Deserialize)]
 struct Config {
 	include: Option<Vec<String>>,
+	name: Option<String>,
+	author: Option<String>,
+	/// Usually a string but some mods write it as a bare number
+	version: Option<toml::Value>,
+	description: Option<String>,
+	dll: Option<OneOrMany>,
+	enabled: Option<bool>,
+}
+
+impl Config {
+	fn version(&self) -> Option<String> {
+		match &self.version {
+			Some(toml::Value::String(version)) => Some(version.clone()),
+			Some(version) => Some(version.to_string()),
+			None => None,
+		}
+	}
+
+	fn dlls(&self) -> Vec<String> {
+		match &self.dll {
+			Some(OneOrMany::One(dll)) => vec![dll.clone()],
+			Some(OneOrMany::Many(dlls)) => dlls.clone(),
+			None => Vec::new(),
+		}
+	}
+}
+
+async fn record_mod_config(
+	post_id: i32,
+	path: String,
+	config: &Config,
+	state: &AppState,
+) -> Result<(), ExtractionError> {
+	sqlx::query!(
+		"INSERT INTO mod_configs (post_id, path, name, author, version, description, dll, enabled) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+		post_id,
+		path,
+		config.name,
+		config.author,
+		config.version(),
+		config.description,
+		&config.dlls(),
+		config.enabled.unwrap_or(true)
+	)
+	.execute(&state.db)
+	.await
+	.map_err(|e| ExtractionError::Transient(e.to_string()))?;
+
+	Ok(())
 }
 
 pub const ROM_DIRS: [&'static str; 31] = [
@@ -85,6 +550,19 @@ pub const ROM_DIRS: [&'static str; 31] = [
 	"rom_switch_tw",
 ];
 
+/// Game a ROM folder belongs to, plain `rom` is shared by all of them
+fn rom_dir_game(rom: &str) -> Option<Game> {
+	if rom.starts_with("rom_steam") {
+		Some(Game::MegaMixPlus)
+	} else if rom.starts_with("rom_ps4") {
+		Some(Game::FutureTone)
+	} else if rom.starts_with("rom_switch") {
+		Some(Game::MegaMix)
+	} else {
+		None
+	}
+}
+
 pub const DB_PREFIXES: [&'static str; 21] = [
 	"mod_",
 	"",
@@ -109,123 +587,496 @@ pub const DB_PREFIXES: [&'static str; 21] = [
 	"dlc2B_",
 ];
 
-pub async fn extract_post_data(post_id: i32, state: AppState) -> Option<()> {
-	let post = Post::get_short(post_id, &state.db).await?;
-	if post.post_type == PostType::Plugin
-		|| post.post_type == PostType::Cover
-		|| post.post_type == PostType::Ui
-	{
-		return None;
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtractionItemKind {
+	Archive,
+	Config,
+	Include,
+	RomDir,
+	PvDb,
+	ModuleTbl,
+	FileList,
+	Dll,
+}
+
+impl std::fmt::Display for ExtractionItemKind {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			ExtractionItemKind::Archive => "Archive",
+			ExtractionItemKind::Config => "config.toml",
+			ExtractionItemKind::Include => "Include",
+			ExtractionItemKind::RomDir => "ROM folder",
+			ExtractionItemKind::PvDb => "pv_db",
+			ExtractionItemKind::ModuleTbl => "Module/customize item table",
+			ExtractionItemKind::FileList => "File list",
+			ExtractionItemKind::Dll => "DLL",
+		})
 	}
+}
 
-	for file in &post.local_files {
-		let file = format!("/pixeldrain/{file}");
-		let file = Path::new(&file);
-		let extension = file.extension()?.to_str()?;
-
-		let dir = temp_dir::TempDir::new().ok()?;
-		let dir = dir.path().to_str()?;
-
-		match extension {
-			"zip" => {
-				Command::new("unzip")
-					.arg(file)
-					.arg("-d")
-					.arg(dir)
-					.output()
-					.await
-					.ok()?;
-				()
-			}
-			"rar" => {
-				Command::new("unrar")
-					.arg("x")
-					.arg(file)
-					.arg(dir)
-					.output()
-					.await
-					.ok()?;
-				()
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExtractionItem {
+	pub kind: ExtractionItemKind,
+	/// Archive name followed by the path inside it
+	pub path: String,
+	/// How many PVs, modules or customize items were indexed from it
+	pub count: Option<usize>,
+	pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ExtractionReport {
+	pub items: Vec<ExtractionItem>,
+}
+
+impl ExtractionReport {
+	fn push(&mut self, kind: ExtractionItemKind, path: String, count: Option<usize>) {
+		self.items.push(ExtractionItem {
+			kind,
+			path,
+			count,
+			error: None,
+		});
+	}
+
+	fn push_error<S: Into<String>>(&mut self, kind: ExtractionItemKind, path: String, error: S) {
+		self.items.push(ExtractionItem {
+			kind,
+			path,
+			count: None,
+			error: Some(error.into()),
+		});
+	}
+}
+
+/// Why extracting a post stopped, or why one of its tables wasn't indexed
+pub enum ExtractionError {
+	/// The post was deleted after it was queued, so there is nothing to retry
+	MissingPost,
+	/// Storage, the database or the search index failed, trying again later may work
+	Transient(String),
+	/// The mod's own data is broken, this only goes in the report
+	Invalid(String),
+}
+
+impl std::fmt::Display for ExtractionError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ExtractionError::MissingPost => write!(f, "Post no longer exists"),
+			ExtractionError::Transient(error) | ExtractionError::Invalid(error) => {
+				write!(f, "{error}")
 			}
-			"7z" => {
-				Command::new("7z")
-					.arg("x")
-					.arg(file)
-					.arg(format!("-o{dir}"))
-					.output()
-					.await
-					.ok()?;
-				()
+		}
+	}
+}
+
+/// Removes everything extract_post_data indexed for a post
+pub async fn clear_post_data(post_id: i32, state: &AppState) {
+	let pvs = state.meilisearch.index("pvs");
+	_ = meilisearch_sdk::documents::DocumentDeletionQuery::new(&pvs)
+		.with_filter(&format!("post={}", post_id))
+		.execute::<MeilisearchPv>()
+		.await;
+
+	let modules = state.meilisearch.index("modules");
+	_ = meilisearch_sdk::documents::DocumentDeletionQuery::new(&modules)
+		.with_filter(&format!("post_id={}", post_id))
+		.execute::<MeilisearchModule>()
+		.await;
+
+	let cstm_items = state.meilisearch.index("cstm_items");
+	_ = meilisearch_sdk::documents::DocumentDeletionQuery::new(&cstm_items)
+		.with_filter(&format!("post_id={}", post_id))
+		.execute::<MeilisearchCstmItem>()
+		.await;
+
+	_ = sqlx::query!("DELETE FROM mod_configs WHERE post_id = $1", post_id)
+		.execute(&state.db)
+		.await;
+	_ = sqlx::query!("DELETE FROM post_dlls WHERE post_id = $1", post_id)
+		.execute(&state.db)
+		.await;
+}
+
+#[derive(Default)]
+struct PeVersionInfo {
+	file_version: Option<String>,
+	product_version: Option<String>,
+	product_name: Option<String>,
+	company_name: Option<String>,
+	file_description: Option<String>,
+}
+
+/// Reads the version resource of a DLL, anything missing is left empty
+fn pe_version_info(data: &[u8]) -> PeVersionInfo {
+	let Ok(file) = pelite::PeFile::from_bytes(data) else {
+		return PeVersionInfo::default();
+	};
+	let Ok(version_info) = file
+		.resources()
+		.map_err(|_| ())
+		.and_then(|resources| resources.version_info().map_err(|_| ()))
+	else {
+		return PeVersionInfo::default();
+	};
+
+	let language = version_info.translation().first().copied();
+	let value = |key: &str| {
+		language
+			.and_then(|language| version_info.value(language, key))
+			.map(|value| value.trim_end_matches('\0').trim().to_string())
+			.filter(|value| !value.is_empty())
+	};
+	let fixed = version_info.fixed();
+
+	PeVersionInfo {
+		file_version: fixed
+			.map(|fixed| fixed.dwFileVersion.to_string())
+			.or_else(|| value("FileVersion")),
+		product_version: fixed
+			.map(|fixed| fixed.dwProductVersion.to_string())
+			.or_else(|| value("ProductVersion")),
+		product_name: value("ProductName"),
+		company_name: value("CompanyName"),
+		file_description: value("FileDescription"),
+	}
+}
+
+async fn record_dll(
+	post_id: i32,
+	file: &Path,
+	path: String,
+	state: &AppState,
+) -> Result<(), String> {
+	let data = tokio::fs::read(file).await.map_err(|e| e.to_string())?;
+	let sha256 = format!("{:x}", sha2::Sha256::digest(&data));
+	let info = pe_version_info(&data);
+	let name = file
+		.file_name()
+		.map(|name| name.to_string_lossy().to_string())
+		.unwrap_or_default();
+
+	sqlx::query!(
+		"INSERT INTO post_dlls (post_id, path, name, sha256, file_version, product_version, product_name, company_name, file_description) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+		post_id,
+		path,
+		name,
+		sha256,
+		info.file_version,
+		info.product_version,
+		info.product_name,
+		info.company_name,
+		info.file_description
+	)
+	.execute(&state.db)
+	.await
+	.map_err(|e| e.to_string())?;
+
+	Ok(())
+}
+
+/// Stores the file listing of every archive of a post, for every post type
+async fn record_archive_files(post: &Post, state: &AppState, report: &mut ExtractionReport) {
+	_ = sqlx::query!("DELETE FROM archive_files WHERE post_id = $1", post.id)
+		.execute(&state.db)
+		.await;
+
+	for local_file in &post.local_files {
+		let archive_name = local_file.split('/').last().unwrap_or(local_file);
+		let entries = match crate::archive::list(state.storage.local_path(local_file)).await {
+			Ok(entries) => entries,
+			Err(crate::archive::ArchiveError::Unsupported(_)) => continue,
+			Err(e) => {
+				report.push_error(
+					ExtractionItemKind::FileList,
+					String::from(archive_name),
+					e.to_string(),
+				);
+				continue;
 			}
-			_ => {
+		};
+
+		let paths = entries
+			.iter()
+			.map(|entry| entry.path.clone())
+			.collect::<Vec<_>>();
+		let sizes = entries
+			.iter()
+			.map(|entry| entry.size as i64)
+			.collect::<Vec<_>>();
+		let compressed_sizes = entries
+			.iter()
+			.map(|entry| entry.compressed_size.map(|size| size as i64))
+			.collect::<Vec<_>>();
+		let result = sqlx::query!(
+			r#"
+			INSERT INTO archive_files (post_id, archive, path, size, compressed_size)
+			SELECT $1, $2, * FROM UNNEST($3::text[], $4::bigint[], $5::bigint[])
+			"#,
+			post.id,
+			archive_name,
+			&paths,
+			&sizes,
+			&compressed_sizes as &[Option<i64>]
+		)
+		.execute(&state.db)
+		.await;
+		match result {
+			Ok(_) => report.push(
+				ExtractionItemKind::FileList,
+				String::from(archive_name),
+				Some(entries.len()),
+			),
+			Err(e) => report.push_error(
+				ExtractionItemKind::FileList,
+				String::from(archive_name),
+				e.to_string(),
+			),
+		}
+	}
+}
+
+/// Resolves a dll or include path from a mod's config.toml, refusing anything that points outside of the extracted archive
+async fn resolve_mod_path(
+	root: &Path,
+	mod_folder: &Path,
+	path: &str,
+	missing: &'static str,
+) -> Result<std::path::PathBuf, &'static str> {
+	let outside = "Points outside of the archive";
+	if !Path::new(path).components().all(|component| {
+		matches!(
+			component,
+			std::path::Component::Normal(_) | std::path::Component::CurDir
+		)
+	}) {
+		return Err(outside);
+	}
+
+	let Ok(resolved) = tokio::fs::canonicalize(mod_folder.join(path)).await else {
+		return Err(missing);
+	};
+	if !resolved.starts_with(root) {
+		return Err(outside);
+	}
+	Ok(resolved)
+}
+
+/// Indexes everything in a post's archives, problems with the mod itself end up in the report while anything worth retrying is returned as an error
+pub async fn extract_post_data(
+	post_id: i32,
+	state: AppState,
+) -> Result<ExtractionReport, ExtractionError> {
+	let Some(post) = Post::get_short(post_id, &state.db).await else {
+		return Err(ExtractionError::MissingPost);
+	};
+	// A missing archive is most likely storage being unavailable rather than the file being gone
+	for local_file in &post.local_files {
+		if !state.storage.local_path(local_file).exists() {
+			return Err(ExtractionError::Transient(format!(
+				"{local_file} is not available in storage"
+			)));
+		}
+	}
+
+	let mut report = ExtractionReport::default();
+	record_archive_files(&post, &state, &mut report).await;
+	if post.post_type == PostType::Cover || post.post_type == PostType::Ui {
+		return Ok(report);
+	}
+
+	let mut games = Vec::new();
+
+	for local_file in &post.local_files {
+		let archive_name = local_file.split('/').last().unwrap_or(local_file);
+		let file = state.storage.local_path(local_file);
+		let dir =
+			temp_dir::TempDir::new().map_err(|e| ExtractionError::Transient(e.to_string()))?;
+
+		match crate::archive::extract(file.clone(), dir.path().to_path_buf()).await {
+			Ok(()) => report.push(
+				ExtractionItemKind::Archive,
+				String::from(archive_name),
+				None,
+			),
+			Err(crate::archive::ArchiveError::Unsupported(_)) => continue,
+			Err(e) => {
+				report.push_error(
+					ExtractionItemKind::Archive,
+					String::from(archive_name),
+					e.to_string(),
+				);
 				continue;
 			}
 		}
+		// Canonical so paths from config.toml can be checked against it once symlinks are resolved
+		let root = tokio::fs::canonicalize(dir.path())
+			.await
+			.map_err(|e| ExtractionError::Transient(e.to_string()))?;
+		let root = root.as_path();
+		// Paths in the report are shown relative to the archive rather than the temp dir
+		let display = |path: &Path| {
+			format!(
+				"{archive_name}/{}",
+				path.strip_prefix(root).unwrap_or(path).display()
+			)
+		};
 
-		for file in walkdir::WalkDir::new(dir).into_iter().filter(|file| {
+		let mut found_config = false;
+		for file in walkdir::WalkDir::new(root).into_iter().filter(|file| {
 			if let Ok(file) = &file {
 				file.path().ends_with("config.toml")
 			} else {
 				false
 			}
 		}) {
-			let file = file.ok()?;
+			let Ok(file) = file else {
+				continue;
+			};
+			found_config = true;
+			// config.toml is DivaModLoader's, which only exists for Mega Mix+
+			if !games.contains(&Game::MegaMixPlus) {
+				games.push(Game::MegaMixPlus);
+			}
 			let file = file.path();
-			let data = tokio::fs::read_to_string(file).await.ok()?;
-			let config: Config = toml::from_str(&data).ok()?;
+			let data = match tokio::fs::read_to_string(file).await {
+				Ok(data) => data,
+				Err(e) => {
+					report.push_error(ExtractionItemKind::Config, display(file), e.to_string());
+					continue;
+				}
+			};
+			let config: Config = match toml::from_str(&data) {
+				Ok(config) => config,
+				Err(e) => {
+					report.push_error(ExtractionItemKind::Config, display(file), e.to_string());
+					continue;
+				}
+			};
+			record_mod_config(post_id, display(file), &config, &state).await?;
+			let Some(mod_folder) = file.parent() else {
+				continue;
+			};
+
+			let dlls = config.dlls();
+			for dll in &dlls {
+				let dll = match resolve_mod_path(root, mod_folder, dll, "File does not exist").await
+				{
+					Ok(dll) => dll,
+					Err(e) => {
+						report.push_error(
+							ExtractionItemKind::Dll,
+							display(&mod_folder.join(dll)),
+							e,
+						);
+						continue;
+					}
+				};
+				match record_dll(post_id, &dll, display(&dll), &state).await {
+					Ok(()) => report.push(ExtractionItemKind::Dll, display(&dll), None),
+					Err(e) => report.push_error(ExtractionItemKind::Dll, display(&dll), e),
+				}
+			}
+
 			let Some(include) = config.include else {
+				// Plugins usually only load DLLs and have nothing to include
+				if dlls.is_empty() {
+					report.push_error(ExtractionItemKind::Config, display(file), "No include list");
+				} else {
+					report.push(ExtractionItemKind::Config, display(file), None);
+				}
 				continue;
 			};
+			report.push(ExtractionItemKind::Config, display(file), None);
 
 			for include in &include {
+				let include_folder = match resolve_mod_path(
+					root,
+					mod_folder,
+					include,
+					"Folder does not exist",
+				)
+				.await
+				{
+					Ok(include_folder) => include_folder,
+					Err(e) => {
+						report.push_error(
+							ExtractionItemKind::Include,
+							display(&mod_folder.join(include)),
+							e,
+						);
+						continue;
+					}
+				};
+				report.push(ExtractionItemKind::Include, display(&include_folder), None);
+
 				for rom in &ROM_DIRS {
-					let folder = format!("{}/{include}/{rom}", file.parent()?.to_str()?);
-					let path = Path::new(&folder);
-					if !path.exists() {
+					let folder = include_folder.join(rom);
+					if !folder.exists() {
 						continue;
 					}
+					report.push(ExtractionItemKind::RomDir, display(&folder), None);
+					if let Some(game) = rom_dir_game(rom) {
+						if !games.contains(&game) {
+							games.push(game);
+						}
+					}
+
 					for prefix in &DB_PREFIXES {
-						let pv_db = format!("{folder}/{prefix}pv_db.txt");
-						let path = Path::new(&pv_db);
-						if path.exists() {
-							if let Ok(data) = tokio::fs::read_to_string(&path).await {
-								parse_pv_db(&data, post_id, state.clone()).await;
+						let pv_db = folder.join(format!("{prefix}pv_db.txt"));
+						if pv_db.exists() {
+							let result = match tokio::fs::read_to_string(&pv_db).await {
+								Ok(data) => parse_pv_db(&data, post_id, state.clone()).await,
+								Err(e) => Err(ExtractionError::Invalid(e.to_string())),
+							};
+							match result {
+								Ok(count) => report.push(
+									ExtractionItemKind::PvDb,
+									display(&pv_db),
+									Some(count),
+								),
+								Err(ExtractionError::Invalid(e)) => {
+									report.push_error(ExtractionItemKind::PvDb, display(&pv_db), e)
+								}
+								Err(e) => return Err(e),
 							}
 						}
 
-						let module_tbl = format!("{folder}/{prefix}gm_module_tbl.farc");
-						let module_tbl = Path::new(&module_tbl);
+						let module_tbl = folder.join(format!("{prefix}gm_module_tbl.farc"));
 						let customize_item_tbl =
-							format!("{folder}/{prefix}gm_customize_item_tbl.farc");
-						let customize_item_tbl = Path::new(&customize_item_tbl);
+							folder.join(format!("{prefix}gm_customize_item_tbl.farc"));
 						if module_tbl.exists() || customize_item_tbl.exists() {
-							let chritm_prop = format!("{folder}/{prefix}chritm_prop.farc");
-							let chritm_prop = Path::new(&chritm_prop);
-							let str_array = format!("{folder}/lang2/mod_str_array.toml");
-							let str_array = Path::new(&str_array);
+							let chritm_prop = folder.join(format!("{prefix}chritm_prop.farc"));
+							let str_array = folder.join("lang2/mod_str_array.toml");
+							let path = if module_tbl.exists() {
+								display(&module_tbl)
+							} else {
+								display(&customize_item_tbl)
+							};
 
 							let module_tbl = if module_tbl.exists() {
-								Some(module_tbl)
+								Some(module_tbl.as_path())
 							} else {
 								None
 							};
 							let customize_item_tbl = if customize_item_tbl.exists() {
-								Some(customize_item_tbl)
+								Some(customize_item_tbl.as_path())
 							} else {
 								None
 							};
 							let chritm_prop = if chritm_prop.exists() {
-								Some(chritm_prop)
+								Some(chritm_prop.as_path())
 							} else {
 								None
 							};
 							let str_array = if str_array.exists() {
-								Some(str_array)
+								Some(str_array.as_path())
 							} else {
 								None
 							};
 
-							parse_module_db(
+							match parse_module_db(
 								module_tbl,
 								customize_item_tbl,
 								chritm_prop,
@@ -233,19 +1084,67 @@ pub async fn extract_post_data(post_id: i32, state: AppState) -> Option<()> {
 								post_id,
 								state.clone(),
 							)
-							.await;
+							.await
+							{
+								Ok(count) => {
+									report.push(ExtractionItemKind::ModuleTbl, path, Some(count))
+								}
+								Err(ExtractionError::Invalid(e)) => {
+									report.push_error(ExtractionItemKind::ModuleTbl, path, e)
+								}
+								Err(e) => return Err(e),
+							}
 						}
 					}
 				}
 			}
 		}
+
+		if !found_config {
+			report.push_error(
+				ExtractionItemKind::Archive,
+				String::from(archive_name),
+				"No config.toml found",
+			);
+
+			// Console mods are laid out as ROM folders to copy over the game's own
+			for dir in walkdir::WalkDir::new(root)
+				.max_depth(3)
+				.into_iter()
+				.filter_map(|dir| dir.ok())
+				.filter(|dir| dir.file_type().is_dir())
+			{
+				let name = dir.file_name().to_string_lossy();
+				if !ROM_DIRS.contains(&name.as_ref()) {
+					continue;
+				}
+				if let Some(game) = rom_dir_game(&name) {
+					if !games.contains(&game) {
+						games.push(game);
+					}
+				}
+			}
+		}
 	}
 
+	let games = games
+		.into_iter()
+		.map(|game| game as i32)
+		.collect::<Vec<_>>();
+	sqlx::query!(
+		"UPDATE posts SET inferred_games = $2 WHERE id = $1",
+		post_id,
+		&games
+	)
+	.execute(&state.db)
+	.await
+	.map_err(|e| ExtractionError::Transient(e.to_string()))?;
+
 	optimise_reservations(ReservationType::Song, &state).await;
 	optimise_reservations(ReservationType::Module, &state).await;
 	optimise_reservations(ReservationType::CstmItem, &state).await;
 
-	Some(())
+	Ok(report)
 }
 
 async fn parse_module_db<P: AsRef<Path>>(
@@ -255,10 +1154,15 @@ async fn parse_module_db<P: AsRef<Path>>(
 	str_array: Option<P>,
 	post_id: i32,
 	state: AppState,
-) -> Option<()> {
-	let module_db =
+) -> Result<usize, ExtractionError> {
+	let Some(module_db) =
 		module_db::ModuleDb::from_files(module_tbl, customize_item_tbl, chritm_prop, str_array)
-			.await?;
+			.await
+	else {
+		return Err(ExtractionError::Invalid(String::from(
+			"Could not read the module or customize item tables",
+		)));
+	};
 
 	let modules = module_db
 		.modules
@@ -287,7 +1191,7 @@ async fn parse_module_db<P: AsRef<Path>>(
 		.with_limit(2000)
 		.execute::<MeilisearchModule>()
 		.await
-		.ok()?;
+		.map_err(|e| ExtractionError::Transient(e.to_string()))?;
 
 	let mut modules = modules
 		.into_iter()
@@ -321,14 +1225,14 @@ async fn parse_module_db<P: AsRef<Path>>(
 		.index("modules")
 		.add_or_update(&modules, Some("uid"))
 		.await
-		.ok()?;
+		.map_err(|e| ExtractionError::Transient(e.to_string()))?;
 
 	let base = meilisearch_sdk::search::SearchQuery::new(&state.meilisearch.index("cstm_items"))
 		.with_filter("post_id=-1")
 		.with_limit(2000)
 		.execute::<MeilisearchCstmItem>()
 		.await
-		.ok()?;
+		.map_err(|e| ExtractionError::Transient(e.to_string()))?;
 
 	let cstm_items = cstm_items
 		.into_iter()
@@ -345,16 +1249,25 @@ async fn parse_module_db<P: AsRef<Path>>(
 		.index("cstm_items")
 		.add_or_update(&cstm_items, Some("uid"))
 		.await
-		.ok()?;
+		.map_err(|e| ExtractionError::Transient(e.to_string()))?;
 
-	Some(())
+	Ok(modules.len() + cstm_items.len())
 }
 
-async fn parse_pv_db(data: &str, post_id: i32, state: AppState) -> Option<()> {
-	let pv_db = pv_db::PvDb::from_str(data)?;
+async fn parse_pv_db(data: &str, post_id: i32, state: AppState) -> Result<usize, ExtractionError> {
+	let Some(pv_db) = pv_db::PvDb::from_str(data) else {
+		return Err(ExtractionError::Invalid(String::from(
+			"Could not parse pv_db",
+		)));
+	};
+
+	let pv_values = pv_db_values(data);
 
 	let mut documents = Vec::new();
 	for (id, entry) in pv_db.pvs.iter() {
+		let empty = BTreeMap::new();
+		let values = pv_values.get(&(*id as i32)).unwrap_or(&empty);
+		let charts = pv_charts(values);
 		let mut levels = [const { None }; 5];
 		if let Some(difficulties) = &entry.difficulty {
 			if let Some(easys) = &difficulties.easy {
@@ -397,6 +1310,13 @@ async fn parse_pv_db(data: &str, post_id: i32, state: AppState) -> Option<()> {
 			song_info: entry.songinfo.clone(),
 			song_info_en: entry.songinfo_en.clone(),
 			levels,
+			stars: PvStars::from_charts(&charts),
+			charts,
+			performers: pv_performers(values),
+			bpm: values.get("bpm").and_then(|bpm| bpm.parse().ok()),
+			song_file_name: values.get("song_file_name").cloned(),
+			sabi: pv_sabi(values),
+			date: values.get("date").and_then(|date| date.parse().ok()),
 		});
 	}
 
@@ -405,7 +1325,7 @@ async fn parse_pv_db(data: &str, post_id: i32, state: AppState) -> Option<()> {
 		.with_limit(300)
 		.execute::<MeilisearchPv>()
 		.await
-		.unwrap();
+		.map_err(|e| ExtractionError::Transient(e.to_string()))?;
 
 	let pvs = documents
 		.into_iter()
@@ -423,9 +1343,9 @@ async fn parse_pv_db(data: &str, post_id: i32, state: AppState) -> Option<()> {
 		.index("pvs")
 		.add_or_update(&pvs, Some("uid"))
 		.await
-		.unwrap();
+		.map_err(|e| ExtractionError::Transient(e.to_string()))?;
 
-	Some(())
+	Ok(pvs.len())
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -438,9 +1358,27 @@ pub struct Pv {
 	pub song_info: Option<pv_db::SongInfo>,
 	pub song_info_en: Option<pv_db::SongInfo>,
 	pub levels: [Option<pv_db::Level>; 5],
+	pub charts: Vec<PvChart>,
+	pub performers: Vec<PvPerformer>,
+	pub bpm: Option<i32>,
+	pub song_file_name: Option<String>,
+	pub sabi: Option<PvSabi>,
+	pub date: Option<i32>,
+	pub stars: PvStars,
 }
 
 impl Pv {
+	/// The YYYYMMDD date as YYYY-MM-DD
+	pub fn release_date(&self) -> Option<String> {
+		let date = self.date?;
+		Some(format!(
+			"{:04}-{:02}-{:02}",
+			date / 10000,
+			date / 100 % 100,
+			date % 100
+		))
+	}
+
 	pub fn has_music(&self) -> bool {
 		if let Some(song_info) = &self.song_info {
 			if let Some(music) = &song_info.music {
@@ -582,9 +1520,18 @@ pub struct PvSearch {
 }
 
 pub async fn search_pvs(
-	axum_extra::extract::Query(query): axum_extra::extract::Query<SearchParams>,
+	axum_extra::extract::Query(query): axum_extra::extract::Query<PvSearchParams>,
+	user: Option<User>,
 	State(state): State<AppState>,
 ) -> Result<Json<PvSearch>, (StatusCode, String)> {
+	check_raw_filter(&query.filter, &user, &state.config)?;
+	find_pvs(query, &state).await.map(Json)
+}
+
+pub async fn find_pvs(
+	query: PvSearchParams,
+	state: &AppState,
+) -> Result<PvSearch, (StatusCode, String)> {
 	let index = state.meilisearch.index("pvs");
 	let mut search = meilisearch_sdk::search::SearchQuery::new(&index);
 
@@ -593,13 +1540,16 @@ pub async fn search_pvs(
 	search.limit = query.limit;
 	search.offset = query.offset;
 
-	search.sort = Some(&["pv_id:asc"]);
+	let level_sort;
+	let mut sort = vec![];
+	if let (Some(level), Some(order)) = (query.level, query.level_order) {
+		level_sort = format!("{}:{order}", level.attribute());
+		sort.push(level_sort.as_str());
+	}
+	sort.push("pv_id:asc");
+	search.sort = Some(&sort);
 
-	let filter = if let Some(filter) = &query.filter {
-		format!("{filter}")
-	} else {
-		String::new()
-	};
+	let filter = query.meilisearch_filter()?;
 
 	search.filter = Some(meilisearch_sdk::search::Filter::new(sqlx::Either::Left(
 		filter.as_str(),
@@ -652,11 +1602,18 @@ pub async fn search_pvs(
 			song_info: pv.song_info,
 			song_info_en: pv.song_info_en,
 			levels: pv.levels,
+			charts: pv.charts,
+			performers: pv.performers,
+			bpm: pv.bpm,
+			song_file_name: pv.song_file_name,
+			sabi: pv.sabi,
+			date: pv.date,
+			stars: pv.stars,
 			post,
 		})
 	}
 
-	Ok(Json(PvSearch { pvs: vec, posts }))
+	Ok(PvSearch { pvs: vec, posts })
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -667,8 +1624,17 @@ pub struct ModuleSearch {
 
 pub async fn search_modules(
 	axum_extra::extract::Query(query): axum_extra::extract::Query<SearchParams>,
+	user: Option<User>,
 	State(state): State<AppState>,
 ) -> Result<Json<ModuleSearch>, (StatusCode, String)> {
+	check_raw_filter(&query.filter, &user, &state.config)?;
+	find_modules(query, &state).await.map(Json)
+}
+
+pub async fn find_modules(
+	query: SearchParams,
+	state: &AppState,
+) -> Result<ModuleSearch, (StatusCode, String)> {
 	let index = state.meilisearch.index("modules");
 	let mut search = meilisearch_sdk::search::SearchQuery::new(&index);
 
@@ -679,11 +1645,7 @@ pub async fn search_modules(
 
 	search.sort = Some(&["module_id:asc"]);
 
-	let filter = if let Some(filter) = &query.filter {
-		format!("{filter}")
-	} else {
-		String::new()
-	};
+	let filter = query.meilisearch_filter("module_id")?;
 
 	search.filter = Some(meilisearch_sdk::search::Filter::new(sqlx::Either::Left(
 		filter.as_str(),
@@ -740,10 +1702,10 @@ pub async fn search_modules(
 		})
 	}
 
-	Ok(Json(ModuleSearch {
+	Ok(ModuleSearch {
 		modules: vec,
 		posts,
-	}))
+	})
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -755,8 +1717,17 @@ pub struct CstmItemSearch {
 
 pub async fn search_cstm_items(
 	axum_extra::extract::Query(query): axum_extra::extract::Query<SearchParams>,
+	user: Option<User>,
 	State(state): State<AppState>,
 ) -> Result<Json<CstmItemSearch>, (StatusCode, String)> {
+	check_raw_filter(&query.filter, &user, &state.config)?;
+	find_cstm_items(query, &state).await.map(Json)
+}
+
+pub async fn find_cstm_items(
+	query: SearchParams,
+	state: &AppState,
+) -> Result<CstmItemSearch, (StatusCode, String)> {
 	let index = state.meilisearch.index("cstm_items");
 	let mut search = meilisearch_sdk::search::SearchQuery::new(&index);
 
@@ -767,11 +1738,7 @@ pub async fn search_cstm_items(
 
 	search.sort = Some(&["customize_item_id:asc"]);
 
-	let filter = if let Some(filter) = &query.filter {
-		format!("{filter}")
-	} else {
-		String::new()
-	};
+	let filter = query.meilisearch_filter("customize_item_id")?;
 
 	search.filter = Some(meilisearch_sdk::search::Filter::new(sqlx::Either::Left(
 		filter.as_str(),
@@ -871,14 +1838,14 @@ pub async fn search_cstm_items(
 			.intersperse(String::from(" OR "))
 			.collect::<String>();
 
-		let Json(modules) = crate::api::ids::search_modules(
-			axum_extra::extract::Query(crate::api::ids::SearchParams {
-				query: None,
+		let modules = find_modules(
+			SearchParams {
 				filter: Some(filter),
 				limit: Some(pending_bound_modules.len()),
 				offset: Some(0),
-			}),
-			State(state.clone()),
+				..Default::default()
+			},
+			state,
 		)
 		.await
 		.unwrap_or_default();
@@ -904,11 +1871,11 @@ pub async fn search_cstm_items(
 		}
 	}
 
-	Ok(Json(CstmItemSearch {
+	Ok(CstmItemSearch {
 		cstm_items: vec,
 		bound_modules,
 		posts,
-	}))
+	})
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
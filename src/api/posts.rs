@@ -6,53 +6,63 @@ use axum::{
 	response::*,
 };
 use serde::{Deserialize, Serialize};
+use sha2::Digest;
 use tokio::io::AsyncWriteExt;
 
-#[derive(Serialize, Deserialize)]
-struct CloudflareDirectUploadResult {
-	id: String,
-	#[serde(rename = "uploadURL")]
-	upload_url: String,
+pub async fn upload_image(_: User, State(state): State<AppState>) -> Result<String, StatusCode> {
+	match state.images.upload_url().await {
+		Some(url) => Ok(url),
+		None => Err(StatusCode::INTERNAL_SERVER_ERROR),
+	}
 }
 
-#[derive(Serialize, Deserialize)]
-struct CloudflareDirectUpload {
-	success: bool,
-	result: CloudflareDirectUploadResult,
-}
+/// Upload target for backends that store images themselves, answers like Cloudflare's direct upload
+pub async fn store_image(
+	_: User,
+	State(state): State<AppState>,
+	mut multipart: Multipart,
+) -> Result<Json<crate::images::ImageUploadResponse>, StatusCode> {
+	while let Ok(Some(field)) = multipart.next_field().await {
+		if field.name() != Some("file") {
+			continue;
+		}
+		let Ok(data) = field.bytes().await else {
+			return Err(StatusCode::BAD_REQUEST);
+		};
+		let result = state.images.store(&data).await;
+		return match result {
+			Ok(result) => Ok(Json(crate::images::ImageUploadResponse {
+				success: true,
+				result: Some(result),
+				errors: Vec::new(),
+			})),
+			Err(message) => Ok(Json(crate::images::ImageUploadResponse {
+				success: false,
+				result: None,
+				errors: vec![crate::images::ImageUploadMessage { message }],
+			})),
+		};
+	}
 
-pub async fn upload_image(_: User, State(state): State<AppState>) -> Result<String, StatusCode> {
-	let cloudflare_url = format!(
-		"https://api.cloudflare.com/client/v4/accounts/{}/images/v2/direct_upload",
-		state.config.cloudflare_account_id
-	);
-
-	let response = reqwest::Client::new()
-		.post(&cloudflare_url)
-		.header(
-			header::AUTHORIZATION.to_string(),
-			format!("Bearer {}", state.config.cloudflare_image_token),
-		)
-		.send()
-		.await;
+	Err(StatusCode::BAD_REQUEST)
+}
 
-	let response = match response {
-		Ok(response) => response,
-		Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-	};
-	if !response.status().is_success() {
-		return Err(StatusCode::INTERNAL_SERVER_ERROR);
-	}
-	let response = response.json::<CloudflareDirectUpload>().await;
-	let response = match response {
-		Ok(response) => response,
-		Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+pub async fn get_image(
+	Path((id, variant)): Path<(String, String)>,
+	State(state): State<AppState>,
+) -> Result<Response, StatusCode> {
+	let Some((content_type, data)) = state.images.load(&id, &variant).await else {
+		return Err(StatusCode::NOT_FOUND);
 	};
-	if response.success {
-		Ok(response.result.upload_url)
-	} else {
-		Err(StatusCode::INTERNAL_SERVER_ERROR)
-	}
+
+	Ok((
+		[
+			(header::CONTENT_TYPE, content_type),
+			(header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+		],
+		data,
+	)
+		.into_response())
 }
 
 #[derive(Serialize, Deserialize)]
@@ -64,6 +74,139 @@ pub struct PostUploadData {
 	pub filenames: Option<Vec<String>>,
 	pub image: Option<String>,
 	pub images_extra: Option<Vec<String>>,
+	/// Expected SHA-256 of each file, checked once the file has been fully received
+	pub hashes: Option<Vec<Option<String>>>,
+	/// Only used for new posts, keeps the post hidden until it is published
+	pub draft: Option<bool>,
+	/// When a new draft should publish itself
+	#[serde(default, with = "time::serde::rfc3339::option")]
+	pub publish_at: Option<time::OffsetDateTime>,
+	/// What changed in this upload, kept with the version it creates. Metadata edits replace the changelog of the current version
+	pub changelog: Option<String>,
+	/// Replaces the post's tags when given
+	pub tags: Option<Vec<String>>,
+	/// Replaces the games the post works with when given, an empty list leaves it to the extractor
+	pub games: Option<Vec<Game>>,
+	/// Replaces the oldest DivaModLoader version the post works with when given, an empty one clears it
+	pub min_dml_version: Option<String>,
+}
+
+/// Most tags a post can have
+pub const MAX_TAGS: usize = 10;
+const MAX_TAG_LENGTH: usize = 32;
+
+/// Lowercases a tag and squashes its whitespace, so "MM+  UI" and "mm+ ui" are the same tag
+pub fn normalize_tag(tag: &str) -> Option<String> {
+	let tag = tag
+		.split_whitespace()
+		.collect::<Vec<_>>()
+		.join(" ")
+		.to_lowercase();
+	if tag.is_empty()
+		|| tag.chars().count() > MAX_TAG_LENGTH
+		|| !tag
+			.chars()
+			.all(|c| c.is_alphanumeric() || " +-.'&".contains(c))
+	{
+		return None;
+	}
+	Some(tag)
+}
+
+/// Normalises and de-duplicates a post's tags
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>, String> {
+	let mut normalized = Vec::new();
+	for tag in tags {
+		let Some(tag) = normalize_tag(tag) else {
+			return Err(format!("Invalid tag {tag}"));
+		};
+		if !normalized.contains(&tag) {
+			normalized.push(tag);
+		}
+	}
+	if normalized.len() > MAX_TAGS {
+		return Err(format!("Posts can have at most {MAX_TAGS} tags"));
+	}
+	Ok(normalized)
+}
+
+/// Numeric form of a DivaModLoader version so it can be range filtered, 1.2.3 is 1002003
+pub fn dml_version_code(version: &str) -> Option<u32> {
+	let parts = version
+		.trim()
+		.trim_start_matches('v')
+		.split('.')
+		.map(|part| part.parse::<u32>().ok().filter(|part| *part < 1000))
+		.collect::<Option<Vec<_>>>()?;
+	if parts.len() > 3 {
+		return None;
+	}
+	Some((0..3).fold(0, |code, i| {
+		code * 1000 + parts.get(i).copied().unwrap_or(0)
+	}))
+}
+
+fn parse_min_dml_version(version: &str) -> Result<Option<String>, String> {
+	let version = version.trim().trim_start_matches('v');
+	if version.is_empty() {
+		return Ok(None);
+	}
+	if dml_version_code(version).is_none() {
+		return Err(format!("Invalid DivaModLoader version {version}"));
+	}
+	Ok(Some(String::from(version)))
+}
+
+/// Updates whichever of the games and minimum DivaModLoader version were given
+async fn set_compatibility(
+	post_id: i32,
+	games: &Option<Vec<Game>>,
+	min_dml_version: Option<Option<String>>,
+	db: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<(), sqlx::Error> {
+	if let Some(games) = games {
+		let games = games.iter().map(|game| *game as i32).collect::<Vec<_>>();
+		sqlx::query!(
+			"UPDATE posts SET games = $2 WHERE id = $1",
+			post_id,
+			if games.is_empty() { None } else { Some(games) }
+		)
+		.execute(db)
+		.await?;
+	}
+	if let Some(min_dml_version) = min_dml_version {
+		sqlx::query!(
+			"UPDATE posts SET min_dml_version = $2 WHERE id = $1",
+			post_id,
+			min_dml_version
+		)
+		.execute(db)
+		.await?;
+	}
+	Ok(())
+}
+
+async fn set_tags(
+	post_id: i32,
+	tags: &[String],
+	db: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<(), sqlx::Error> {
+	sqlx::query!("DELETE FROM post_tags WHERE post_id = $1", post_id)
+		.execute(db)
+		.await?;
+	sqlx::query!(
+		"INSERT INTO post_tags (post_id, tag) SELECT $1, UNNEST($2::text[])",
+		post_id,
+		tags
+	)
+	.execute(db)
+	.await?;
+	Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UploadResume {
+	pub session: i32,
 }
 
 pub async fn edit(
@@ -84,6 +227,22 @@ pub async fn edit(
 	if !authors.iter().any(|u| u.user_id == user.id) {
 		return Err(StatusCode::BAD_REQUEST)?;
 	}
+	if post.publish_at.is_some()
+		&& !Post::get_short(post_id, &state.db)
+			.await
+			.is_some_and(|post| post.draft)
+	{
+		return Err(StatusCode::BAD_REQUEST);
+	}
+
+	let tags = match &post.tags {
+		Some(tags) => Some(normalize_tags(tags).map_err(|_| StatusCode::BAD_REQUEST)?),
+		None => None,
+	};
+	let min_dml_version = match &post.min_dml_version {
+		Some(version) => Some(parse_min_dml_version(version).map_err(|_| StatusCode::BAD_REQUEST)?),
+		None => None,
+	};
 
 	sqlx::query!(
 		"UPDATE posts SET name = $2, text = $3, type = $4 WHERE id = $1",
@@ -96,43 +255,123 @@ pub async fn edit(
 	.await
 	.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-	if let Some(post) = Post::get_short(post_id, &state.db).await {
-		_ = state
-			.meilisearch
-			.index("posts")
-			.add_or_update(&[post], None)
-			.await;
-	};
+	if let Some(changelog) = post
+		.changelog
+		.filter(|changelog| !changelog.trim().is_empty())
+	{
+		// Kept apart from the changelog the files were uploaded with, so every edit adds to the history
+		let now = time::OffsetDateTime::now_utc();
+		sqlx::query!(
+			"INSERT INTO post_changelogs (post_id, version, text, time) SELECT $1, COALESCE(MAX(version), 1), $2, $3 FROM post_versions WHERE post_id = $1",
+			post_id,
+			changelog,
+			time::PrimitiveDateTime::new(now.date(), now.time())
+		)
+		.execute(&state.db)
+		.await
+		.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	}
+
+	if let Some(tags) = tags {
+		set_tags(post_id, &tags, &state.db)
+			.await
+			.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+	}
+
+	set_compatibility(post_id, &post.games, min_dml_version, &state.db)
+		.await
+		.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+	update_search_index(post_id, &state).await;
 
 	Ok(())
 }
 
-pub async fn get_download_link(filepath: &str) -> Option<String> {
-	let command = tokio::process::Command::new("rclone")
-		.arg("link")
-		.arg(format!("pixeldrainfs:/divamodarchive/{}", filepath))
-		.arg("--config=/etc/rclone-mnt.conf")
-		.output()
-		.await;
-	let Ok(command) = command else {
-		return None;
-	};
-	if !command.status.success() {
-		return None;
+pub const UPLOAD_PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UploadMessage {
+	Ready {
+		version: u32,
+		session: i32,
+		file: usize,
+		offset: u64,
+	},
+	Progress {
+		file: usize,
+		offset: u64,
+	},
+	Error {
+		code: UploadErrorCode,
+		message: String,
+	},
+	/// Sent before done when uploaded files are identical to ones on someone else's post
+	Duplicates {
+		duplicates: Vec<DuplicateFile>,
+	},
+	Done {
+		post_id: i32,
+	},
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DuplicateFile {
+	pub file: String,
+	pub post_id: i32,
+	pub post_name: String,
+	/// Duplicates of drafts only go in the admin report, the uploader can't see them
+	#[serde(skip)]
+	pub draft: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadErrorCode {
+	Unauthorized,
+	InvalidRequest,
+	SessionNotFound,
+	InvalidImage,
+	Storage,
+	HashMismatch,
+	InvalidArchive,
+	Database,
+	Disconnected,
+}
+
+pub struct UploadError {
+	pub code: UploadErrorCode,
+	pub message: String,
+}
+
+impl UploadError {
+	pub fn new<S: Into<String>>(code: UploadErrorCode, message: S) -> Self {
+		Self {
+			code,
+			message: message.into(),
+		}
 	}
-	let Ok(path) = String::from_utf8(command.stdout) else {
-		return None;
+}
+
+async fn send_upload_message(socket: &mut ws::WebSocket, message: &UploadMessage) {
+	let Ok(message) = serde_json::to_string(message) else {
+		return;
 	};
+	_ = socket.send(ws::Message::Text(message)).await;
+}
 
-	if !path.starts_with("https://pixeldrain.com/d/") {
-		return None;
+async fn recv_text(socket: &mut ws::WebSocket) -> Result<String, UploadError> {
+	match socket.recv().await {
+		Some(Ok(ws::Message::Text(message))) => Ok(message),
+		Some(Ok(_)) => Err(UploadError::new(
+			UploadErrorCode::InvalidRequest,
+			"Expected a text message",
+		)),
+		_ => Err(UploadError::new(
+			UploadErrorCode::Disconnected,
+			"Connection closed",
+		)),
 	}
-
-	let download = path.trim().replace(
-		"https://pixeldrain.com/d/",
-		"https://pixeldrain.com/api/filesystem/",
-	);
-	Some(format!("{download}?download"))
 }
 
 pub async fn upload_ws(ws: ws::WebSocketUpgrade, State(state): State<AppState>) -> Response {
@@ -140,239 +379,918 @@ pub async fn upload_ws(ws: ws::WebSocketUpgrade, State(state): State<AppState>)
 }
 
 pub async fn real_upload_ws(mut socket: ws::WebSocket, state: AppState) {
-	let Some(Ok(message)) = socket.recv().await else {
-		return;
+	let message = match handle_upload(&mut socket, &state).await {
+		Ok(post_id) => UploadMessage::Done { post_id },
+		Err(error) => UploadMessage::Error {
+			code: error.code,
+			message: error.message,
+		},
 	};
 
-	let ws::Message::Text(message) = message else {
-		return;
-	};
+	send_upload_message(&mut socket, &message).await;
+	_ = socket.close().await;
+}
 
-	let Ok(user) = User::parse(&message, &state).await else {
-		return;
-	};
+async fn handle_upload(socket: &mut ws::WebSocket, state: &AppState) -> Result<i32, UploadError> {
+	let message = recv_text(socket).await?;
+	let user = User::parse(&message, state)
+		.await
+		.map_err(|_| UploadError::new(UploadErrorCode::Unauthorized, "Invalid or expired token"))?;
 
-	let Some(Ok(message)) = socket.recv().await else {
-		return;
-	};
+	let message = recv_text(socket).await?;
 
-	let ws::Message::Text(message) = message else {
-		return;
-	};
+	let resuming = serde_json::from_str::<UploadResume>(&message).ok();
+	let (session_id, params, filepaths, mut hashes, mut completed) = if let Some(resume) = &resuming
+	{
+		let session = sqlx::query!(
+			"SELECT data, filepaths, hashes, completed FROM upload_sessions WHERE id = $1 AND user_id = $2",
+			resume.session,
+			user.id
+		)
+		.fetch_one(&state.db)
+		.await
+		.map_err(|_| {
+			UploadError::new(
+				UploadErrorCode::SessionNotFound,
+				format!("No upload session {} for this user", resume.session),
+			)
+		})?;
+		let params = serde_json::from_str::<PostUploadData>(&session.data)
+			.map_err(|e| UploadError::new(UploadErrorCode::Database, e.to_string()))?;
 
-	let Ok(params) = serde_json::from_str::<PostUploadData>(&message) else {
-		return;
-	};
+		// Resuming keeps the session from being expired, see jobs::run_scheduler
+		let now = time::OffsetDateTime::now_utc();
+		_ = sqlx::query!(
+			"UPDATE upload_sessions SET time = $2 WHERE id = $1",
+			resume.session,
+			time::PrimitiveDateTime::new(now.date(), now.time())
+		)
+		.execute(&state.db)
+		.await;
 
-	let Some(filenames) = params.filenames else {
-		return;
-	};
-	let Some(image) = params.image else {
-		return;
-	};
-	if let Some(post_id) = params.id {
-		let Ok(authors) = sqlx::query!(
-			"SELECT user_id FROM post_authors WHERE post_id = $1",
-			post_id
+		(
+			resume.session,
+			params,
+			session.filepaths,
+			session.hashes,
+			session.completed as usize,
 		)
-		.fetch_all(&state.db)
-		.await
-		else {
-			return;
+	} else {
+		let params = serde_json::from_str::<PostUploadData>(&message).map_err(|e| {
+			UploadError::new(
+				UploadErrorCode::InvalidRequest,
+				format!("Invalid post data: {e}"),
+			)
+		})?;
+
+		let Some(filenames) = &params.filenames else {
+			return Err(UploadError::new(
+				UploadErrorCode::InvalidRequest,
+				"Missing filenames",
+			));
+		};
+		let Some(image) = &params.image else {
+			return Err(UploadError::new(
+				UploadErrorCode::InvalidRequest,
+				"Missing image",
+			));
 		};
-		if !authors.iter().any(|u| u.user_id == user.id) {
-			return;
+		if let Some(tags) = &params.tags {
+			normalize_tags(tags)
+				.map_err(|e| UploadError::new(UploadErrorCode::InvalidRequest, e))?;
+		}
+		if let Some(version) = &params.min_dml_version {
+			parse_min_dml_version(version)
+				.map_err(|e| UploadError::new(UploadErrorCode::InvalidRequest, e))?;
+		}
+		if let Some(filename) = filenames.iter().find(|filename| {
+			filename.is_empty() || *filename == ".." || filename.contains(['/', '\\'])
+		}) {
+			return Err(UploadError::new(
+				UploadErrorCode::InvalidRequest,
+				format!("Invalid filename {filename}"),
+			));
+		}
+		if let Some(post_id) = params.id {
+			let authors = sqlx::query!(
+				"SELECT user_id FROM post_authors WHERE post_id = $1",
+				post_id
+			)
+			.fetch_all(&state.db)
+			.await
+			.map_err(|e| UploadError::new(UploadErrorCode::Database, e.to_string()))?;
+			if !authors.iter().any(|u| u.user_id == user.id) {
+				return Err(UploadError::new(
+					UploadErrorCode::Unauthorized,
+					format!("You are not an author of post {post_id}"),
+				));
+			}
 		}
-	}
 
-	if !image.starts_with("https://divamodarchive.com/cdn-cgi/imagedelivery")
-		|| reqwest::get(&image).await.is_err()
-	{
-		return;
-	}
-	if let Some(extra_images) = &params.images_extra {
-		for image in extra_images {
-			if !image.starts_with("https://divamodarchive.com/cdn-cgi/imagedelivery")
-				|| reqwest::get(image).await.is_err()
-			{
-				return;
+		if params.publish_at.is_some() {
+			let draft = match params.id {
+				Some(post_id) => Post::get_short(post_id, &state.db)
+					.await
+					.is_some_and(|post| post.draft),
+				None => params.draft.unwrap_or(false),
+			};
+			if !draft {
+				return Err(UploadError::new(
+					UploadErrorCode::InvalidRequest,
+					"Only drafts can be scheduled",
+				));
 			}
 		}
-	}
 
-	let mut filepaths = Vec::new();
-	for filename in &filenames {
-		let filepath = format!("{}/{}", user.id, filename);
-		_ = tokio::fs::create_dir(format!("/pixeldrain/{}", user.id)).await;
-		let Ok(mut file) = tokio::fs::File::create(&format!("/pixeldrain/{}", &filepath)).await
-		else {
-			return;
+		for image in std::iter::once(image).chain(params.images_extra.iter().flatten()) {
+			if !state.images.is_hosted(image) {
+				return Err(UploadError::new(
+					UploadErrorCode::InvalidImage,
+					format!("{image} was not uploaded to DMA"),
+				));
+			}
+			if !state.images.exists(image).await {
+				return Err(UploadError::new(
+					UploadErrorCode::InvalidImage,
+					format!("Could not fetch {image}"),
+				));
+			}
+		}
+
+		let session_id = sqlx::query!(
+			r#"SELECT nextval(pg_get_serial_sequence('upload_sessions', 'id')) AS "id!""#
+		)
+		.fetch_one(&state.db)
+		.await
+		.map_err(|e| UploadError::new(UploadErrorCode::Database, e.to_string()))?
+		.id as i32;
+
+		// Every upload gets its own directory, so no upload can overwrite the files of another post or an older version
+		let directory = format!("{}/uploads/{}", user.id, session_id);
+		let filepaths = filenames
+			.iter()
+			.map(|filename| format!("{directory}/{filename}"))
+			.collect::<Vec<_>>();
+		let hashes = (0..filepaths.len())
+			.map(|i| {
+				params
+					.hashes
+					.as_ref()
+					.and_then(|hashes| hashes.get(i).cloned().flatten())
+					.map(|hash| hash.to_lowercase())
+					.unwrap_or_default()
+			})
+			.collect::<Vec<_>>();
+
+		let data = serde_json::to_string(&params)
+			.map_err(|e| UploadError::new(UploadErrorCode::InvalidRequest, e.to_string()))?;
+		let now = time::OffsetDateTime::now_utc();
+		let time = time::PrimitiveDateTime::new(now.date(), now.time());
+
+		sqlx::query!(
+			"INSERT INTO upload_sessions (id, user_id, data, filepaths, hashes, time) VALUES ($1, $2, $3, $4, $5, $6)",
+			session_id,
+			user.id,
+			data,
+			&filepaths,
+			&hashes,
+			time
+		)
+		.execute(&state.db)
+		.await
+		.map_err(|e| UploadError::new(UploadErrorCode::Database, e.to_string()))?;
+
+		(session_id, params, filepaths, hashes, 0)
+	};
+
+	// Only the file that was in progress when the connection dropped is continued, any later ones start over
+	let mut resume_current = resuming.is_some();
+	while completed < filepaths.len() {
+		let filepath = &filepaths[completed];
+		let file = if resume_current {
+			state.storage.append(filepath).await
+		} else {
+			state.storage.put(filepath).await
+		};
+		resume_current = false;
+		let Some(mut file) = file else {
+			return Err(UploadError::new(
+				UploadErrorCode::Storage,
+				format!("Could not create {filepath}"),
+			));
 		};
-		_ = socket.send(ws::Message::Text(String::from("Ready"))).await;
+		let mut offset = file
+			.metadata()
+			.await
+			.map_err(|e| UploadError::new(UploadErrorCode::Storage, e.to_string()))?
+			.len();
+		send_upload_message(
+			socket,
+			&UploadMessage::Ready {
+				version: UPLOAD_PROTOCOL_VERSION,
+				session: session_id,
+				file: completed,
+				offset,
+			},
+		)
+		.await;
 
+		let mut finished = false;
 		while let Some(message) = socket.recv().await {
-			let message = match message {
-				Ok(message) => message,
-				Err(e) => {
-					dbg!(e);
-					_ = socket.close().await;
-					return;
-				}
-			};
+			let message = message
+				.map_err(|e| UploadError::new(UploadErrorCode::Disconnected, e.to_string()))?;
 
 			if let ws::Message::Binary(chunk) = message {
-				let Ok(_) = file.write_all(&chunk).await else {
-					_ = socket.close().await;
-					return;
-				};
-				let Ok(_) = file.sync_data().await else {
-					_ = socket.close().await;
-					return;
-				};
-				_ = socket.send(ws::Message::Text(String::from("Ready"))).await;
+				file.write_all(&chunk)
+					.await
+					.map_err(|e| UploadError::new(UploadErrorCode::Storage, e.to_string()))?;
+				file.sync_data()
+					.await
+					.map_err(|e| UploadError::new(UploadErrorCode::Storage, e.to_string()))?;
+				offset += chunk.len() as u64;
+				send_upload_message(
+					socket,
+					&UploadMessage::Progress {
+						file: completed,
+						offset,
+					},
+				)
+				.await;
 			} else if let ws::Message::Close(_) = message {
-				_ = socket.close().await;
-				return;
+				break;
 			} else {
+				finished = true;
 				break;
 			}
 		}
 
-		_ = file.sync_all().await;
+		_ = file.sync_all().await;
+		drop(file);
+
+		// The connection dropped mid file, the client can pick it back up with the session id
+		if !finished {
+			return Err(UploadError::new(
+				UploadErrorCode::Disconnected,
+				format!("Connection closed during {filepath}"),
+			));
+		}
+
+		let Some(hash) = state.storage.sha256(filepath).await else {
+			return Err(UploadError::new(
+				UploadErrorCode::Storage,
+				format!("Could not read back {filepath}"),
+			));
+		};
+		if !hashes[completed].is_empty() && hashes[completed] != hash {
+			_ = state.storage.put(filepath).await;
+			return Err(UploadError::new(
+				UploadErrorCode::HashMismatch,
+				format!("{filepath} does not match the expected hash"),
+			));
+		}
+		// From here on the session holds the hashes of what was actually stored
+		hashes[completed] = hash;
+
+		completed += 1;
+		let now = time::OffsetDateTime::now_utc();
+		_ = sqlx::query!(
+			"UPDATE upload_sessions SET completed = $2, hashes = $3, time = $4 WHERE id = $1",
+			session_id,
+			completed as i32,
+			&hashes,
+			time::PrimitiveDateTime::new(now.date(), now.time())
+		)
+		.execute(&state.db)
+		.await;
+	}
+
+	let post_type = PostType::from(params.post_type);
+	for filepath in &filepaths {
+		let filename = filepath.split('/').last().unwrap_or(filepath);
+		let entries = match crate::archive::validate(state.storage.local_path(filepath)).await {
+			Ok(entries) => entries,
+			Err(e) => {
+				discard_upload(session_id, &filepaths, state).await;
+				return Err(UploadError::new(
+					UploadErrorCode::InvalidArchive,
+					format!("{filename}: {e}"),
+				));
+			}
+		};
+
+		let has_config = entries
+			.iter()
+			.any(|entry| entry == "config.toml" || entry.ends_with("/config.toml"));
+		if (post_type == PostType::Song || post_type == PostType::Module) && !has_config {
+			discard_upload(session_id, &filepaths, state).await;
+			return Err(UploadError::new(
+				UploadErrorCode::InvalidArchive,
+				format!("{filename} does not contain a DivaModLoader config.toml"),
+			));
+		}
+	}
+
+	let Some(image) = params.image else {
+		return Err(UploadError::new(
+			UploadErrorCode::InvalidRequest,
+			"Missing image",
+		));
+	};
+	let mut images = Vec::new();
+	images.push(image);
+	if let Some(extra_images) = params.images_extra {
+		for image in extra_images {
+			images.push(image);
+		}
+	}
+
+	let now = time::OffsetDateTime::now_utc();
+	let time = time::PrimitiveDateTime::new(now.date(), now.time());
+
+	let publish_at = params
+		.publish_at
+		.map(|publish_at| publish_at.to_offset(time::UtcOffset::UTC))
+		.map(|publish_at| time::PrimitiveDateTime::new(publish_at.date(), publish_at.time()));
+
+	let mut downloads = Vec::new();
+	let mut sizes = Vec::new();
+
+	for filepath in &filepaths {
+		let Some(download) = state.storage.public_link(filepath).await else {
+			return Err(UploadError::new(
+				UploadErrorCode::Storage,
+				format!("Failed to get public link for {filepath}"),
+			));
+		};
+		downloads.push(download);
+
+		let Some(size) = state.storage.size(filepath).await else {
+			return Err(UploadError::new(
+				UploadErrorCode::Storage,
+				format!("Failed to get size of {filepath}"),
+			));
+		};
+		sizes.push(size as i64);
+	}
+
+	let post_id = if let Some(post_id) = params.id {
+		if Post::get_short(post_id, &state.db).await.is_none() {
+			return Err(UploadError::new(
+				UploadErrorCode::InvalidRequest,
+				format!("Post {post_id} no longer exists"),
+			));
+		}
+
+		sqlx::query!(
+				"UPDATE posts SET name = $2, text = $3, type = $4, files = $5, images = $6, time = $7, local_files = $8, file_hashes = $9, file_sizes = $10 WHERE id = $1",
+				post_id,
+				params.name,
+				params.text,
+				params.post_type,
+				&downloads,
+				&images,
+				time,
+				&filepaths,
+				&hashes,
+				&sizes,
+			)
+			.execute(&state.db)
+			.await
+			.map_err(|e| UploadError::new(UploadErrorCode::Database, e.to_string()))?;
+
+		crate::api::ids::clear_post_data(post_id, &state).await;
+
+		post_id
+	} else {
+		let id = sqlx::query!("INSERT INTO posts (name, text, images, files, time, type, local_files, file_hashes, file_sizes, draft, publish_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) RETURNING ID", params.name, params.text, &images, &downloads, time, params.post_type, &filepaths, &hashes, &sizes, params.draft.unwrap_or(false), publish_at)
+				.fetch_one(&state.db)
+				.await
+				.map_err(|e| UploadError::new(UploadErrorCode::Database, e.to_string()))?;
+
+		_ = sqlx::query!(
+			"INSERT INTO post_authors (post_id, user_id) VALUES ($1, $2)",
+			id.id,
+			user.id,
+		)
+		.execute(&state.db)
+		.await;
+
+		id.id
+	};
+
+	sqlx::query!(
+		r#"
+		INSERT INTO post_versions (post_id, version, changelog, files, local_files, file_hashes, file_sizes, time)
+		SELECT $1, COALESCE(MAX(version), 0) + 1, $2, $3, $4, $5, $6, $7 FROM post_versions WHERE post_id = $1
+		"#,
+		post_id,
+		params.changelog.filter(|changelog| !changelog.trim().is_empty()),
+		&downloads,
+		&filepaths,
+		&hashes,
+		&sizes,
+		time
+	)
+	.execute(&state.db)
+	.await
+	.map_err(|e| UploadError::new(UploadErrorCode::Database, e.to_string()))?;
+
+	if let Some(tags) = &params.tags {
+		let tags = normalize_tags(tags)
+			.map_err(|e| UploadError::new(UploadErrorCode::InvalidRequest, e))?;
+		set_tags(post_id, &tags, &state.db)
+			.await
+			.map_err(|e| UploadError::new(UploadErrorCode::Database, e.to_string()))?;
+	}
+
+	let min_dml_version = match &params.min_dml_version {
+		Some(version) => Some(
+			parse_min_dml_version(version)
+				.map_err(|e| UploadError::new(UploadErrorCode::InvalidRequest, e))?,
+		),
+		None => None,
+	};
+	set_compatibility(post_id, &params.games, min_dml_version, &state.db)
+		.await
+		.map_err(|e| UploadError::new(UploadErrorCode::Database, e.to_string()))?;
+
+	update_search_index(post_id, &state).await;
+
+	let duplicates = find_duplicate_files(post_id, &filepaths, &hashes, state).await;
+	if !duplicates.is_empty() {
+		for duplicate in &duplicates {
+			let text = format!(
+				"Automatic report: {} is identical to a file on post {} ({})",
+				duplicate.file, duplicate.post_id, duplicate.post_name
+			);
+			_ = sqlx::query!(
+				"INSERT INTO reports (post_id, text, time) SELECT $1, $2, $3 WHERE NOT EXISTS (SELECT 1 FROM reports WHERE post_id = $1 AND user_id IS NULL AND text = $2)",
+				post_id,
+				text,
+				time
+			)
+			.execute(&state.db)
+			.await;
+		}
+		let duplicates = duplicates
+			.into_iter()
+			.filter(|duplicate| !duplicate.draft)
+			.collect::<Vec<_>>();
+		if !duplicates.is_empty() {
+			send_upload_message(socket, &UploadMessage::Duplicates { duplicates }).await;
+		}
+	}
+
+	_ = sqlx::query!("DELETE FROM upload_sessions WHERE id = $1", session_id)
+		.execute(&state.db)
+		.await;
+
+	// Drafts are extracted when they get published, so their songs and modules don't show up early
+	if Post::get_short(post_id, &state.db)
+		.await
+		.is_some_and(|post| !post.draft)
+	{
+		crate::jobs::enqueue_extraction(post_id, &state.db).await;
+	}
+
+	Ok(post_id)
+}
+
+/// Drops an upload that can't be published, keeping any files a version of an existing post still points at
+pub async fn discard_upload(session_id: i32, filepaths: &[String], state: &AppState) {
+	_ = sqlx::query!("DELETE FROM upload_sessions WHERE id = $1", session_id)
+		.execute(&state.db)
+		.await;
+
+	for filepath in filepaths {
+		let in_use = sqlx::query!(
+			"SELECT post_id FROM post_versions WHERE $1 = ANY(local_files)",
+			filepath
+		)
+		.fetch_optional(&state.db)
+		.await
+		.map_or(true, |post| post.is_some());
+		if !in_use {
+			state.storage.delete(filepath).await;
+		}
+	}
+}
+
+/// Files of a post that are byte identical to files on posts not sharing any of its authors
+pub async fn find_duplicate_files(
+	post_id: i32,
+	filepaths: &[String],
+	hashes: &[String],
+	state: &AppState,
+) -> Vec<DuplicateFile> {
+	let Ok(matches) = sqlx::query!(
+		r#"
+		SELECT p.id, p.name, p.draft, h.hash as "hash!"
+		FROM posts p, unnest(p.file_hashes) AS h(hash)
+		WHERE p.file_hashes && $2 AND h.hash = ANY($2) AND p.id != $1
+		AND NOT EXISTS (
+			SELECT 1 FROM post_authors pa
+			WHERE pa.post_id = p.id AND pa.user_id IN (SELECT user_id FROM post_authors WHERE post_id = $1)
+		)
+		"#,
+		post_id,
+		hashes
+	)
+	.fetch_all(&state.db)
+	.await
+	else {
+		return Vec::new();
+	};
+
+	let mut duplicates = Vec::new();
+	for duplicate in matches {
+		let Some(i) = hashes.iter().position(|hash| *hash == duplicate.hash) else {
+			continue;
+		};
+		let Some(file) = filepaths[i].split('/').last() else {
+			continue;
+		};
+		duplicates.push(DuplicateFile {
+			file: String::from(file),
+			post_id: duplicate.id,
+			post_name: duplicate.name,
+			draft: duplicate.draft,
+		});
+	}
+
+	duplicates
+}
+
+/// Keeps the posts index in line with the database, drafts are removed from it
+pub async fn update_search_index(post_id: i32, state: &AppState) {
+	let Some(post) = Post::get_short(post_id, &state.db).await else {
+		return;
+	};
+	let index = state.meilisearch.index("posts");
+	if post.draft {
+		_ = index.delete_document(post_id).await;
+	} else {
+		_ = index
+			.add_or_update(&[MeilisearchPost::from(&post)], None)
+			.await;
+	}
+}
+
+/// Makes a draft public, bumping its time so it shows up as new
+pub async fn publish_post(post_id: i32, state: &AppState) {
+	let now = time::OffsetDateTime::now_utc();
+	let time = time::PrimitiveDateTime::new(now.date(), now.time());
+
+	let Ok(result) = sqlx::query!(
+		"UPDATE posts SET draft = false, publish_at = NULL, time = $2 WHERE id = $1 AND draft",
+		post_id,
+		time
+	)
+	.execute(&state.db)
+	.await
+	else {
+		return;
+	};
+	if result.rows_affected() == 0 {
+		return;
+	}
+
+	update_search_index(post_id, state).await;
+	crate::jobs::enqueue_extraction(post_id, &state.db).await;
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PublishRequest {
+	/// Publishes immediately when missing or in the past
+	#[serde(default, with = "time::serde::rfc3339::option")]
+	pub publish_at: Option<time::OffsetDateTime>,
+}
+
+pub async fn publish(
+	Path(id): Path<i32>,
+	user: User,
+	State(state): State<AppState>,
+	Json(request): Json<PublishRequest>,
+) -> Result<Json<Post>, StatusCode> {
+	let Some(post) = Post::get_short(id, &state.db).await else {
+		return Err(StatusCode::NOT_FOUND);
+	};
+	if !post.authors.iter().any(|u| u.id == user.id) {
+		return Err(StatusCode::UNAUTHORIZED);
+	}
+	if !post.draft {
+		return Err(StatusCode::BAD_REQUEST);
+	}
+
+	match request.publish_at {
+		Some(publish_at) if publish_at > time::OffsetDateTime::now_utc() => {
+			let publish_at = publish_at.to_offset(time::UtcOffset::UTC);
+			_ = sqlx::query!(
+				"UPDATE posts SET publish_at = $2 WHERE id = $1",
+				id,
+				time::PrimitiveDateTime::new(publish_at.date(), publish_at.time())
+			)
+			.execute(&state.db)
+			.await;
+		}
+		_ => publish_post(id, &state).await,
+	}
+
+	let Some(post) = Post::get_short(id, &state.db).await else {
+		return Err(StatusCode::NOT_FOUND);
+	};
+	Ok(Json(post))
+}
+
+/// Only authors and admins can see how extraction went, the same as on the post page
+pub async fn get_extraction_status(
+	Path(id): Path<i32>,
+	user: Option<User>,
+	State(state): State<AppState>,
+) -> Result<Json<crate::jobs::ExtractionJob>, StatusCode> {
+	let Some(post) = Post::get_short(id, &state.db).await else {
+		return Err(StatusCode::NOT_FOUND);
+	};
+	// Authors and admins can see drafts too, so this also keeps drafts hidden
+	if !user
+		.as_ref()
+		.is_some_and(|user| post.authors.contains(user) || user.is_admin(&state.config))
+	{
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	let Some(job) = crate::jobs::ExtractionJob::get(id, &state.db).await else {
+		return Err(StatusCode::NOT_FOUND);
+	};
+	Ok(Json(job))
+}
 
-		filepaths.push(filepath);
-	}
+/// Hash identifying a client by its IP, only taken from headers a trusted proxy sets since clients can send any headers they like
+fn client_fingerprint(
+	headers: &header::HeaderMap,
+	addr: std::net::SocketAddr,
+	config: &crate::Config,
+) -> String {
+	let forwarded = match config.trusted_proxy {
+		Some(crate::TrustedProxy::Cloudflare) => headers
+			.get("cf-connecting-ip")
+			.and_then(|ip| ip.to_str().ok())
+			.map(|ip| String::from(ip.trim())),
+		// The proxy appends the address it saw, anything before that came from the client
+		Some(crate::TrustedProxy::Forwarded) => headers
+			.get_all("x-forwarded-for")
+			.iter()
+			.last()
+			.and_then(|ip| ip.to_str().ok())
+			.and_then(|ip| ip.split(',').last())
+			.map(|ip| String::from(ip.trim())),
+		None => None,
+	};
+	let ip = forwarded.unwrap_or(addr.ip().to_string());
 
-	let mut images = Vec::new();
-	images.push(image);
-	if let Some(extra_images) = params.images_extra {
-		for image in extra_images {
-			images.push(image);
-		}
-	}
+	let mut hasher = sha2::Sha256::new();
+	hasher.update(config.fingerprint_key.as_bytes());
+	hasher.update(ip.as_bytes());
+	format!("{:x}", hasher.finalize())
+}
 
+/// Logs a download, returning whether it counts, which it only does for the client's first download of the post within the window, per-file counts work the same way for each file
+async fn record_download(
+	post_id: i32,
+	variant: i32,
+	fingerprint: &str,
+	state: &AppState,
+) -> Result<bool, sqlx::Error> {
 	let now = time::OffsetDateTime::now_utc();
 	let time = time::PrimitiveDateTime::new(now.date(), now.time());
 
-	let mut downloads = Vec::new();
+	// Holding a lock for the client and post makes parallel requests wait, so only one of them can count
+	let mut transaction = state.db.begin().await?;
+	sqlx::query!(
+		r#"SELECT 1 AS "locked!" FROM pg_advisory_xact_lock($1, hashtext($2))"#,
+		post_id,
+		fingerprint
+	)
+	.fetch_one(&mut *transaction)
+	.await?;
+
+	let event = sqlx::query!(
+		r#"
+		INSERT INTO download_events (post_id, variant, time, fingerprint, counted, variant_counted)
+		SELECT $1, $2, $3, $4, NOT EXISTS (
+			SELECT 1 FROM download_events
+			WHERE post_id = $1 AND fingerprint = $4 AND time >= $5 AND counted
+		), NOT EXISTS (
+			SELECT 1 FROM download_events
+			WHERE post_id = $1 AND fingerprint = $4 AND time >= $5 AND variant = $2 AND variant_counted
+		)
+		RETURNING counted
+		"#,
+		post_id,
+		variant,
+		time,
+		fingerprint,
+		time - state.config.download_window
+	)
+	.fetch_one(&mut *transaction)
+	.await?;
 
-	for filepath in &filepaths {
-		let download = get_download_link(filepath).await;
-		let Some(download) = download else {
-			println!("Failed to get public link for {filepath}");
-			return;
-		};
-		downloads.push(download);
+	if event.counted {
+		// The search index picks this up in batches, see jobs::run_download_indexer
+		sqlx::query!(
+			"UPDATE posts SET download_count = download_count +1 WHERE id = $1",
+			post_id
+		)
+		.execute(&mut *transaction)
+		.await?;
 	}
 
-	let post_id = if let Some(post_id) = params.id {
-		let Some(post) = Post::get_full(post_id, &state.db).await else {
-			return;
-		};
+	transaction.commit().await?;
+	Ok(event.counted)
+}
 
-		_ = sqlx::query!(
-				"UPDATE posts SET name = $2, text = $3, type = $4, files = $5, images = $6, time = $7, local_files = $8 WHERE id = $1",
-				post_id,
-				params.name,
-				params.text,
-				params.post_type,
-				&downloads,
-				&images,
-				time,
-				&filepaths,
-			)
-			.execute(&state.db)
-			.await;
+pub async fn download(
+	Path((id, variant)): Path<(i32, i32)>,
+	user: Option<User>,
+	ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+	headers: header::HeaderMap,
+	State(state): State<AppState>,
+) -> Result<Redirect, StatusCode> {
+	let Some(post) = Post::get_short(id, &state.db).await else {
+		return Err(StatusCode::NOT_FOUND);
+	};
+	if !post.is_visible_to(user.as_ref(), &state.config) {
+		return Err(StatusCode::NOT_FOUND);
+	}
 
-		let pvs = state.meilisearch.index("pvs");
-		_ = meilisearch_sdk::documents::DocumentDeletionQuery::new(&pvs)
-			.with_filter(&format!("post={}", post_id))
-			.execute::<crate::api::ids::MeilisearchPv>()
-			.await;
+	let Some(file) = post.files.get(variant as usize) else {
+		return Err(StatusCode::BAD_REQUEST);
+	};
 
-		let modules = state.meilisearch.index("modules");
-		_ = meilisearch_sdk::documents::DocumentDeletionQuery::new(&modules)
-			.with_filter(&format!("post_id={}", post_id))
-			.execute::<crate::api::ids::MeilisearchModule>()
-			.await;
+	let fingerprint = client_fingerprint(&headers, addr, &state.config);
+	_ = record_download(id, variant, &fingerprint, &state).await;
 
-		let cstm_items = state.meilisearch.index("cstm_items");
-		_ = meilisearch_sdk::documents::DocumentDeletionQuery::new(&cstm_items)
-			.with_filter(&format!("post_id={}", post_id))
-			.execute::<crate::api::ids::MeilisearchCstmItem>()
-			.await;
+	Ok(Redirect::to(file))
+}
 
-		for file in post.local_files {
-			if !filepaths.contains(&file) {
-				_ = tokio::process::Command::new("rclone")
-					.arg("delete")
-					.arg(format!("pixeldrainfs:/divamodarchive/{}", file))
-					.arg("--config=/etc/rclone-mnt.conf")
-					.output()
-					.await;
-			}
-		}
+#[derive(Serialize, Deserialize)]
+pub struct DownloadDay {
+	pub date: time::Date,
+	/// Index into the post's files
+	pub variant: i32,
+	pub count: i64,
+}
 
+#[derive(Serialize, Deserialize)]
+pub struct FileDownloads {
+	pub variant: i32,
+	pub count: i64,
+}
+
+/// Counted downloads of each of a post's files over all time, files nobody has downloaded are left out
+pub async fn file_downloads(post_id: i32, db: &sqlx::Pool<sqlx::Postgres>) -> Vec<FileDownloads> {
+	sqlx::query_as!(
+		FileDownloads,
+		r#"
+		SELECT variant, COUNT(*) AS "count!"
+		FROM download_events
+		WHERE post_id = $1 AND variant_counted
+		GROUP BY variant
+		ORDER BY variant
+		"#,
 		post_id
-	} else {
-		let Ok(id) = sqlx::query!("INSERT INTO posts (name, text, images, files, time, type, local_files) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING ID", params.name, params.text, &images, &downloads, time, params.post_type, &filepaths)
-				.fetch_one(&state.db)
-				.await else {
-					return;
-				};
+	)
+	.fetch_all(db)
+	.await
+	.unwrap_or_default()
+}
 
-		_ = sqlx::query!(
-			"INSERT INTO post_authors (post_id, user_id) VALUES ($1, $2)",
-			id.id,
-			user.id,
-		)
-		.execute(&state.db)
-		.await;
+#[derive(Serialize, Deserialize)]
+pub struct DownloadHistoryParams {
+	pub days: Option<i64>,
+}
 
-		id.id
+/// Counted downloads per day and file over the last `days` days, 30 by default, days without downloads are left out
+pub async fn get_download_history(
+	Path(id): Path<i32>,
+	Query(params): Query<DownloadHistoryParams>,
+	user: Option<User>,
+	State(state): State<AppState>,
+) -> Result<Json<Vec<DownloadDay>>, StatusCode> {
+	let Some(post) = Post::get_short(id, &state.db).await else {
+		return Err(StatusCode::NOT_FOUND);
 	};
+	if !post.is_visible_to(user.as_ref(), &state.config) {
+		return Err(StatusCode::NOT_FOUND);
+	}
 
-	if let Some(post) = Post::get_short(post_id, &state.db).await {
-		_ = state
-			.meilisearch
-			.index("posts")
-			.add_or_update(&[post], None)
-			.await;
+	let days = params.days.unwrap_or(30).clamp(1, 365);
+	let today = time::OffsetDateTime::now_utc().date();
+	let since = (today - time::Duration::days(days - 1)).midnight();
+
+	let history = sqlx::query_as!(
+		DownloadDay,
+		r#"
+		SELECT CAST(time AS date) AS "date!", variant, COUNT(*) AS "count!"
+		FROM download_events
+		WHERE post_id = $1 AND time >= $2 AND variant_counted
+		GROUP BY 1, 2
+		ORDER BY 1, 2
+		"#,
+		id,
+		since
+	)
+	.fetch_all(&state.db)
+	.await
+	.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+	Ok(Json(history))
+}
+
+pub async fn get_post_versions(
+	Path(id): Path<i32>,
+	user: Option<User>,
+	State(state): State<AppState>,
+) -> Result<Json<Vec<PostVersion>>, StatusCode> {
+	let Some(post) = Post::get_short(id, &state.db).await else {
+		return Err(StatusCode::NOT_FOUND);
 	};
+	if !post.is_visible_to(user.as_ref(), &state.config) {
+		return Err(StatusCode::NOT_FOUND);
+	}
 
-	tokio::spawn(crate::api::ids::extract_post_data(post_id, state.clone()));
+	let mut versions = PostVersion::list(id, &state.db).await;
+	for version in &mut versions {
+		for i in 0..version.files.len() {
+			version.files[i] = format!(
+				"https://divamodarchive.com/api/v1/posts/{}/versions/{}/download/{i}",
+				id, version.version
+			);
+			version.local_files[i] = version.local_files[i]
+				.split("/")
+				.last()
+				.map(|s| String::from(s))
+				.unwrap_or(String::new());
+		}
+	}
 
-	_ = socket
-		.send(ws::Message::Text(format!("/post/{post_id}")))
-		.await;
+	Ok(Json(versions))
+}
 
-	_ = socket.close().await;
+pub async fn get_archive_files(
+	Path(id): Path<i32>,
+	user: Option<User>,
+	State(state): State<AppState>,
+) -> Result<Json<Vec<ArchiveFile>>, StatusCode> {
+	let Some(post) = Post::get_short(id, &state.db).await else {
+		return Err(StatusCode::NOT_FOUND);
+	};
+	if !post.is_visible_to(user.as_ref(), &state.config) {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	Ok(Json(ArchiveFile::list(id, &state.db).await))
 }
 
-pub async fn download(
-	Path((id, variant)): Path<(i32, i32)>,
+pub async fn get_changelog(
+	Path(id): Path<i32>,
+	user: Option<User>,
+	State(state): State<AppState>,
+) -> Result<Json<Vec<Changelog>>, StatusCode> {
+	let Some(post) = Post::get_short(id, &state.db).await else {
+		return Err(StatusCode::NOT_FOUND);
+	};
+	if !post.is_visible_to(user.as_ref(), &state.config) {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
+	Ok(Json(Changelog::list(id, &state.db).await))
+}
+
+pub async fn download_version(
+	Path((id, version, variant)): Path<(i32, i32, i32)>,
+	user: Option<User>,
 	State(state): State<AppState>,
 ) -> Result<Redirect, StatusCode> {
 	let Some(post) = Post::get_short(id, &state.db).await else {
 		return Err(StatusCode::NOT_FOUND);
 	};
+	if !post.is_visible_to(user.as_ref(), &state.config) {
+		return Err(StatusCode::NOT_FOUND);
+	}
 
-	_ = sqlx::query!(
-		"UPDATE posts SET download_count = download_count +1 WHERE id = $1",
-		id
+	let Ok(version) = sqlx::query!(
+		"SELECT files FROM post_versions WHERE post_id = $1 AND version = $2",
+		id,
+		version
 	)
-	.execute(&state.db)
-	.await;
-
-	if let Some(post) = Post::get_short(id, &state.db).await {
-		_ = state
-			.meilisearch
-			.index("posts")
-			.add_or_update(&[post], None)
-			.await;
+	.fetch_one(&state.db)
+	.await
+	else {
+		return Err(StatusCode::NOT_FOUND);
 	};
 
-	let Some(file) = post.files.get(variant as usize) else {
+	let Some(file) = version.files.get(variant as usize) else {
 		return Err(StatusCode::BAD_REQUEST);
 	};
 
@@ -383,6 +1301,9 @@ pub async fn like(Path(id): Path<i32>, user: User, State(state): State<AppState>
 	let Some(post) = Post::get_short(id, &state.db).await else {
 		return StatusCode::NOT_FOUND;
 	};
+	if !post.is_visible_to(Some(&user), &state.config) {
+		return StatusCode::NOT_FOUND;
+	}
 
 	let Ok(has_liked) = sqlx::query!(
 		"SELECT COUNT(*) FROM liked_posts WHERE post_id = $1 AND user_id = $2",
@@ -413,24 +1334,22 @@ pub async fn like(Path(id): Path<i32>, user: User, State(state): State<AppState>
 		.await;
 	}
 
-	if let Some(post) = Post::get_short(id, &state.db).await {
-		_ = state
-			.meilisearch
-			.index("posts")
-			.add_or_update(&[post], None)
-			.await;
-	};
+	update_search_index(id, &state).await;
 
 	StatusCode::OK
 }
 
 pub async fn get_post(
 	Path(id): Path<i32>,
+	user: Option<User>,
 	State(state): State<AppState>,
 ) -> Result<Json<Post>, StatusCode> {
 	let Some(mut post) = Post::get_full(id, &state.db).await else {
 		return Err(StatusCode::NOT_FOUND);
 	};
+	if !post.is_visible_to(user.as_ref(), &state.config) {
+		return Err(StatusCode::NOT_FOUND);
+	}
 	for i in 0..post.files.len() {
 		post.files[i] = format!(
 			"https://divamodarchive.com/api/v1/posts/{}/download/{i}",
@@ -461,38 +1380,200 @@ pub async fn get_multiple_posts(
 		.collect::<Vec<_>>()
 		.join(" OR ");
 	let params = SearchParams {
-		query: None,
-		sort: None,
 		filter: Some(filter),
 		limit: Some(posts.post_id.len()),
-		offset: None,
+		..Default::default()
 	};
-	search_posts(axum_extra::extract::Query(params), State(state)).await
+	find_posts(&params, &state).await.map(Json)
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize)]
+pub struct DllParams {
+	pub name: Option<String>,
+	pub sha256: Option<String>,
+}
+
+/// Posts shipping a DLL, matched by file name or hash
+pub async fn find_dll(
+	axum_extra::extract::Query(dll): axum_extra::extract::Query<DllParams>,
+	State(state): State<AppState>,
+) -> Result<Json<Vec<Post>>, (StatusCode, String)> {
+	let mut filters = Vec::new();
+	if let Some(name) = &dll.name {
+		filters.push(format!("dlls.name = {}", quote_filter(name)));
+	}
+	if let Some(sha256) = &dll.sha256 {
+		filters.push(format!(
+			"dlls.sha256 = {}",
+			quote_filter(&sha256.to_lowercase())
+		));
+	}
+	if filters.is_empty() {
+		return Err((
+			StatusCode::BAD_REQUEST,
+			String::from("Either name or sha256 is required"),
+		));
+	}
+
+	let params = SearchParams {
+		filter: Some(filters.join(" OR ")),
+		..Default::default()
+	};
+	find_posts(&params, &state).await.map(Json)
+}
+
+/// Quotes a user supplied value for use in a Meilisearch filter
+pub fn quote_filter(value: &str) -> String {
+	format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Raw filters expose the index layout and Meilisearch's own errors, so only admins may send them
+pub fn check_raw_filter(
+	filter: &Option<String>,
+	user: &Option<User>,
+	config: &crate::Config,
+) -> Result<(), (StatusCode, String)> {
+	if filter.is_some() && !user.as_ref().is_some_and(|user| user.is_admin(config)) {
+		return Err((
+			StatusCode::UNAUTHORIZED,
+			String::from("Raw filters are only available to admins"),
+		));
+	}
+	Ok(())
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct SearchParams {
 	pub query: Option<String>,
+	/// `<field>:<asc|desc>` for time, download_count or like_count
 	pub sort: Option<String>,
+	/// Raw Meilisearch filter, only accepted from admins
 	pub filter: Option<String>,
 	pub limit: Option<usize>,
 	pub offset: Option<usize>,
+	pub post_type: Option<PostType>,
+	/// Name of one of the post's authors
+	pub author: Option<String>,
+	/// Only posts from this day onwards
+	pub after: Option<time::Date>,
+	/// Only posts up to and including this day
+	pub before: Option<time::Date>,
+	/// Only posts with every one of these tags
+	#[serde(default)]
+	pub tag: Vec<String>,
+	pub game: Option<Game>,
+	/// Only posts that work with this DivaModLoader version
+	pub dml_version: Option<String>,
+}
+
+impl SearchParams {
+	/// The requested sort, with newest first breaking ties
+	fn meilisearch_sort(&self) -> Result<Vec<&str>, (StatusCode, String)> {
+		let Some(sort) = &self.sort else {
+			return Ok(vec!["time:desc"]);
+		};
+		let Some((field, order)) = sort.split_once(':') else {
+			return Err((StatusCode::BAD_REQUEST, format!("Invalid sort {sort}")));
+		};
+		if !["time", "download_count", "like_count", "weekly_downloads"].contains(&field)
+			|| !["asc", "desc"].contains(&order)
+		{
+			return Err((StatusCode::BAD_REQUEST, format!("Invalid sort {sort}")));
+		}
+		if field == "time" {
+			Ok(vec![sort])
+		} else {
+			Ok(vec![sort, "time:desc"])
+		}
+	}
+
+	fn meilisearch_filter(&self) -> Result<String, (StatusCode, String)> {
+		let mut filters = Vec::new();
+		if let Some(filter) = &self.filter {
+			filters.push(format!("({filter})"));
+		}
+		if let Some(post_type) = &self.post_type {
+			filters.push(format!(
+				"post_type = {}",
+				serde_json::to_string(post_type).unwrap_or_default()
+			));
+		}
+		if let Some(author) = &self.author {
+			filters.push(format!("authors.name = {}", quote_filter(author)));
+		}
+		if let Some(after) = self.after {
+			filters.push(format!(
+				"timestamp >= {}",
+				after.midnight().assume_utc().unix_timestamp()
+			));
+		}
+		if let Some(before) = self.before {
+			let end = before.next_day().unwrap_or(before).midnight();
+			filters.push(format!("timestamp < {}", end.assume_utc().unix_timestamp()));
+		}
+		for tag in &self.tag {
+			let tag = normalize_tag(tag).unwrap_or_else(|| tag.to_lowercase());
+			filters.push(format!("tags = {}", quote_filter(&tag)));
+		}
+		if let Some(game) = &self.game {
+			filters.push(format!(
+				"games = {}",
+				serde_json::to_string(game).unwrap_or_default()
+			));
+		}
+		if let Some(version) = &self.dml_version {
+			let Some(code) = dml_version_code(version) else {
+				return Err((
+					StatusCode::BAD_REQUEST,
+					format!("Invalid DivaModLoader version {version}"),
+				));
+			};
+			filters.push(format!(
+				"(min_dml_version_code IS NULL OR min_dml_version_code <= {code})"
+			));
+		}
+		Ok(filters.join(" AND "))
+	}
+}
+
+/// How posts are stored in Meilisearch, which can only compare numbers so the time and DivaModLoader version are also kept as numbers
+#[derive(Serialize)]
+pub struct MeilisearchPost<'a> {
+	#[serde(flatten)]
+	pub post: &'a Post,
+	pub timestamp: i64,
+	pub min_dml_version_code: Option<u32>,
+}
+
+impl<'a> From<&'a Post> for MeilisearchPost<'a> {
+	fn from(post: &'a Post) -> Self {
+		Self {
+			post,
+			timestamp: post.time.unix_timestamp(),
+			min_dml_version_code: post.min_dml_version.as_deref().and_then(dml_version_code),
+		}
+	}
 }
 
 pub async fn search_posts(
 	axum_extra::extract::Query(query): axum_extra::extract::Query<SearchParams>,
+	user: Option<User>,
 	State(state): State<AppState>,
 ) -> Result<Json<Vec<Post>>, (StatusCode, String)> {
+	check_raw_filter(&query.filter, &user, &state.config)?;
+	find_posts(&query, &state).await.map(Json)
+}
+
+pub async fn find_posts(
+	query: &SearchParams,
+	state: &AppState,
+) -> Result<Vec<Post>, (StatusCode, String)> {
 	let index = state.meilisearch.index("posts");
 	let mut search = meilisearch_sdk::search::SearchQuery::new(&index);
 
 	search.query = query.query.as_ref().map(|query| query.as_str());
 
-	let filter = if let Some(filter) = &query.filter {
-		format!("{filter}")
-	} else {
-		String::new()
-	};
+	let filter = query.meilisearch_filter()?;
 
 	search.filter = Some(meilisearch_sdk::search::Filter::new(sqlx::Either::Left(
 		filter.as_str(),
@@ -501,12 +1582,7 @@ pub async fn search_posts(
 	search.limit = query.limit;
 	search.offset = query.offset;
 
-	let mut sort = vec![];
-	if let Some(qsort) = &query.sort {
-		sort.push(qsort.as_str());
-	} else {
-		sort.push("time:desc");
-	}
+	let sort = query.meilisearch_sort()?;
 	search.sort = Some(&sort);
 
 	let posts = search
@@ -540,23 +1616,73 @@ pub async fn search_posts(
 		}
 	}
 
-	Ok(Json(vec))
+	Ok(vec)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TagFacet {
+	pub tag: String,
+	pub count: usize,
+}
+
+pub async fn search_tags(
+	axum_extra::extract::Query(query): axum_extra::extract::Query<SearchParams>,
+	user: Option<User>,
+	State(state): State<AppState>,
+) -> Result<Json<Vec<TagFacet>>, (StatusCode, String)> {
+	check_raw_filter(&query.filter, &user, &state.config)?;
+	find_tag_facets(&query, &state).await.map(Json)
+}
+
+/// Tags of the posts a search matches and how many of them have each, most used first
+pub async fn find_tag_facets(
+	query: &SearchParams,
+	state: &AppState,
+) -> Result<Vec<TagFacet>, (StatusCode, String)> {
+	let index = state.meilisearch.index("posts");
+	let mut search = meilisearch_sdk::search::SearchQuery::new(&index);
+
+	search.query = query.query.as_ref().map(|query| query.as_str());
+
+	let filter = query.meilisearch_filter()?;
+
+	search.filter = Some(meilisearch_sdk::search::Filter::new(sqlx::Either::Left(
+		filter.as_str(),
+	)));
+
+	search.limit = Some(0);
+	search.facets = Some(meilisearch_sdk::search::Selectors::Some(&["tags"]));
+
+	let results = search
+		.execute::<Post>()
+		.await
+		.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+	let mut tags = results
+		.facet_distribution
+		.and_then(|mut facets| facets.remove("tags"))
+		.unwrap_or_default()
+		.into_iter()
+		.map(|(tag, count)| TagFacet { tag, count })
+		.collect::<Vec<_>>();
+	tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+
+	Ok(tags)
 }
 
 pub async fn count_posts(
 	axum_extra::extract::Query(query): axum_extra::extract::Query<SearchParams>,
+	user: Option<User>,
 	State(state): State<AppState>,
 ) -> Result<Json<usize>, (StatusCode, String)> {
+	check_raw_filter(&query.filter, &user, &state.config)?;
+
 	let index = state.meilisearch.index("posts");
 	let mut search = meilisearch_sdk::search::SearchQuery::new(&index);
 
 	search.query = query.query.as_ref().map(|query| query.as_str());
 
-	let filter = if let Some(filter) = &query.filter {
-		format!("{filter}")
-	} else {
-		String::new()
-	};
+	let filter = query.meilisearch_filter()?;
 
 	search.filter = Some(meilisearch_sdk::search::Filter::new(sqlx::Either::Left(
 		filter.as_str(),
@@ -565,12 +1691,7 @@ pub async fn count_posts(
 	search.limit = query.limit;
 	search.offset = query.offset;
 
-	let mut sort = vec![];
-	if let Some(qsort) = &query.sort {
-		sort.push(qsort.as_str());
-	} else {
-		sort.push("time:desc");
-	}
+	let sort = query.meilisearch_sort()?;
 	search.sort = Some(&sort);
 
 	let posts = search
@@ -594,13 +1715,10 @@ pub async fn delete_post(
 		return Err(StatusCode::UNAUTHORIZED);
 	}
 
-	for file in post.local_files {
-		_ = tokio::process::Command::new("rclone")
-			.arg("delete")
-			.arg(format!("pixeldrainfs:/divamodarchive/{}", file))
-			.arg("--config=/etc/rclone-mnt.conf")
-			.output()
-			.await;
+	for version in PostVersion::list(post.id, &state.db).await {
+		for file in version.local_files {
+			state.storage.delete(&file).await;
+		}
 	}
 
 	_ = sqlx::query!("DELETE FROM posts WHERE id = $1", post.id)
@@ -613,23 +1731,7 @@ pub async fn delete_post(
 		.delete_document(post.id)
 		.await;
 
-	let pvs = state.meilisearch.index("pvs");
-	_ = meilisearch_sdk::documents::DocumentDeletionQuery::new(&pvs)
-		.with_filter(&format!("post={}", post.id))
-		.execute::<crate::api::ids::MeilisearchPv>()
-		.await;
-
-	let modules = state.meilisearch.index("modules");
-	_ = meilisearch_sdk::documents::DocumentDeletionQuery::new(&modules)
-		.with_filter(&format!("post_id={}", post.id))
-		.execute::<crate::api::ids::MeilisearchModule>()
-		.await;
-
-	let cstm_items = state.meilisearch.index("cstm_items");
-	_ = meilisearch_sdk::documents::DocumentDeletionQuery::new(&cstm_items)
-		.with_filter(&format!("post_id={}", post.id))
-		.execute::<crate::api::ids::MeilisearchCstmItem>()
-		.await;
+	crate::api::ids::clear_post_data(post.id, &state).await;
 
 	Ok(())
 }
@@ -680,6 +1782,9 @@ pub async fn add_dependency(
 	let Some(dependency) = Post::get_short(dependency, &state.db).await else {
 		return Err(StatusCode::NOT_FOUND);
 	};
+	if !dependency.is_visible_to(Some(&user), &state.config) {
+		return Err(StatusCode::NOT_FOUND);
+	}
 
 	if !post.authors.iter().any(|u| u.id == user.id) {
 		return Err(StatusCode::UNAUTHORIZED);
@@ -725,7 +1830,14 @@ pub async fn report(
 	user: User,
 	State(state): State<AppState>,
 	Json(complaint): Json<String>,
-) {
+) -> Result<(), StatusCode> {
+	let Some(post) = Post::get_short(id, &state.db).await else {
+		return Err(StatusCode::NOT_FOUND);
+	};
+	if !post.is_visible_to(Some(&user), &state.config) {
+		return Err(StatusCode::NOT_FOUND);
+	}
+
 	let now = time::OffsetDateTime::now_utc();
 	let time = time::PrimitiveDateTime::new(now.date(), now.time());
 
@@ -738,6 +1850,8 @@ pub async fn report(
 	)
 	.execute(&state.db)
 	.await;
+
+	Ok(())
 }
 
 #[derive(Serialize, Deserialize)]
@@ -752,7 +1866,10 @@ pub async fn comment(
 	State(state): State<AppState>,
 	Json(comment): Json<CommentRequest>,
 ) -> Result<(), StatusCode> {
-	if Post::get_short(id, &state.db).await.is_none() {
+	let Some(post) = Post::get_short(id, &state.db).await else {
+		return Err(StatusCode::NOT_FOUND);
+	};
+	if !post.is_visible_to(Some(&user), &state.config) {
 		return Err(StatusCode::NOT_FOUND);
 	}
 	let now = time::OffsetDateTime::now_utc();
@@ -0,0 +1,507 @@
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+/// Most data a single archive may decompress to
+pub const MAX_EXTRACTED_SIZE: u64 = 16 * 1024 * 1024 * 1024;
+/// Most files and folders a single archive may contain
+pub const MAX_ENTRIES: usize = 50_000;
+
+/// A file inside an archive, folders are left out
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+	pub path: String,
+	pub size: u64,
+	/// Missing when the format compresses files together, like solid 7z blocks
+	pub compressed_size: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum ArchiveError {
+	Unsupported(String),
+	Open(String),
+	Encrypted,
+	TooManyEntries,
+	TooLarge,
+	UnsafePath(String),
+	Symlink(String),
+	Entry { name: String, error: String },
+}
+
+impl std::fmt::Display for ArchiveError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ArchiveError::Unsupported(extension) => {
+				write!(f, "Unsupported archive type {extension}")
+			}
+			ArchiveError::Open(error) => write!(f, "Could not open archive: {error}"),
+			ArchiveError::Encrypted => write!(f, "Archive is password protected"),
+			ArchiveError::TooManyEntries => {
+				write!(f, "Archive contains more than {MAX_ENTRIES} entries")
+			}
+			ArchiveError::TooLarge => write!(
+				f,
+				"Archive extracts to more than {} GiB",
+				MAX_EXTRACTED_SIZE / 1024 / 1024 / 1024
+			),
+			ArchiveError::UnsafePath(name) => {
+				write!(f, "{name} would be extracted outside of the archive")
+			}
+			ArchiveError::Symlink(name) => write!(f, "{name} is a symlink"),
+			ArchiveError::Entry { name, error } => write!(f, "Could not extract {name}: {error}"),
+		}
+	}
+}
+
+/// Extracts `file` into `dir`, picking the format from the extension
+pub async fn extract(file: PathBuf, dir: PathBuf) -> Result<(), ArchiveError> {
+	let extension = file
+		.extension()
+		.and_then(|extension| extension.to_str())
+		.map(|extension| extension.to_lowercase())
+		.unwrap_or_default();
+
+	match extension.as_str() {
+		"zip" => tokio::task::spawn_blocking(move || extract_zip(&file, &dir))
+			.await
+			.map_err(|e| ArchiveError::Open(e.to_string()))?,
+		"7z" => tokio::task::spawn_blocking(move || extract_7z(&file, &dir))
+			.await
+			.map_err(|e| ArchiveError::Open(e.to_string()))?,
+		"rar" => extract_rar(&file, &dir).await,
+		_ => Err(ArchiveError::Unsupported(extension)),
+	}
+}
+
+/// Checks that an archive opens, isn't password protected and stays within the limits without extracting it, returning the names of its entries
+pub async fn validate(file: PathBuf) -> Result<Vec<String>, ArchiveError> {
+	let extension = file
+		.extension()
+		.and_then(|extension| extension.to_str())
+		.map(|extension| extension.to_lowercase())
+		.unwrap_or_default();
+
+	match extension.as_str() {
+		"zip" => tokio::task::spawn_blocking(move || {
+			let file = std::fs::File::open(file).map_err(|e| ArchiveError::Open(e.to_string()))?;
+			let mut archive =
+				zip::ZipArchive::new(file).map_err(|e| ArchiveError::Open(e.to_string()))?;
+			check_zip(&mut archive)
+		})
+		.await
+		.map_err(|e| ArchiveError::Open(e.to_string()))?,
+		"7z" => tokio::task::spawn_blocking(move || {
+			let reader = open_7z(&file)?;
+			let names = check_7z(&reader)?;
+			let encrypted = reader.archive().folders.iter().any(|folder| {
+				folder.coders.iter().any(|coder| {
+					coder.decompression_method_id() == sevenz_rust::SevenZMethod::ID_AES256SHA256
+				})
+			});
+			if encrypted {
+				return Err(ArchiveError::Encrypted);
+			}
+			Ok(names)
+		})
+		.await
+		.map_err(|e| ArchiveError::Open(e.to_string()))?,
+		"rar" => validate_rar(&file).await,
+		_ => Err(ArchiveError::Unsupported(extension)),
+	}
+}
+
+/// Lists the files in an archive with their sizes, without extracting it
+pub async fn list(file: PathBuf) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+	let extension = file
+		.extension()
+		.and_then(|extension| extension.to_str())
+		.map(|extension| extension.to_lowercase())
+		.unwrap_or_default();
+
+	match extension.as_str() {
+		"zip" => tokio::task::spawn_blocking(move || list_zip(&file))
+			.await
+			.map_err(|e| ArchiveError::Open(e.to_string()))?,
+		"7z" => tokio::task::spawn_blocking(move || list_7z(&file))
+			.await
+			.map_err(|e| ArchiveError::Open(e.to_string()))?,
+		"rar" => list_rar(&file).await,
+		_ => Err(ArchiveError::Unsupported(extension)),
+	}
+}
+
+/// Turns an archive entry name into a path relative to the extraction folder, if it stays inside it
+fn enclosed_path(name: &str) -> Option<PathBuf> {
+	let name = name.replace('\\', "/");
+	let mut path = PathBuf::new();
+	for component in Path::new(&name).components() {
+		match component {
+			Component::Normal(part) => path.push(part),
+			Component::CurDir => {}
+			_ => return None,
+		}
+	}
+	Some(path)
+}
+
+/// Copies an entry to disk while keeping the archive under the size limit, regardless of what sizes the archive claims
+fn write_entry(
+	reader: &mut dyn Read,
+	path: &Path,
+	name: &str,
+	extracted: &mut u64,
+) -> Result<(), ArchiveError> {
+	let entry_error = |error: std::io::Error| ArchiveError::Entry {
+		name: String::from(name),
+		error: error.to_string(),
+	};
+
+	if let Some(parent) = path.parent() {
+		std::fs::create_dir_all(parent).map_err(entry_error)?;
+	}
+	let mut output = std::fs::File::create(path).map_err(entry_error)?;
+	let remaining = MAX_EXTRACTED_SIZE - *extracted;
+	let written =
+		std::io::copy(&mut reader.take(remaining + 1), &mut output).map_err(entry_error)?;
+	if written > remaining {
+		return Err(ArchiveError::TooLarge);
+	}
+	*extracted += written;
+
+	Ok(())
+}
+
+fn check_zip(archive: &mut zip::ZipArchive<std::fs::File>) -> Result<Vec<String>, ArchiveError> {
+	if archive.len() > MAX_ENTRIES {
+		return Err(ArchiveError::TooManyEntries);
+	}
+
+	let mut names = Vec::with_capacity(archive.len());
+	let mut declared = 0u64;
+	for i in 0..archive.len() {
+		let entry = archive
+			.by_index_raw(i)
+			.map_err(|e| ArchiveError::Open(e.to_string()))?;
+		if entry.encrypted() {
+			return Err(ArchiveError::Encrypted);
+		}
+		if entry.is_symlink() {
+			return Err(ArchiveError::Symlink(String::from(entry.name())));
+		}
+		if entry.enclosed_name().is_none() {
+			return Err(ArchiveError::UnsafePath(String::from(entry.name())));
+		}
+		declared = declared.saturating_add(entry.size());
+		names.push(entry.name().replace('\\', "/"));
+	}
+	if declared > MAX_EXTRACTED_SIZE {
+		return Err(ArchiveError::TooLarge);
+	}
+
+	Ok(names)
+}
+
+fn extract_zip(file: &Path, dir: &Path) -> Result<(), ArchiveError> {
+	let file = std::fs::File::open(file).map_err(|e| ArchiveError::Open(e.to_string()))?;
+	let mut archive = zip::ZipArchive::new(file).map_err(|e| ArchiveError::Open(e.to_string()))?;
+	check_zip(&mut archive)?;
+
+	let mut extracted = 0;
+	for i in 0..archive.len() {
+		let mut entry = archive.by_index(i).map_err(|e| ArchiveError::Entry {
+			name: format!("entry {i}"),
+			error: e.to_string(),
+		})?;
+		let name = String::from(entry.name());
+		let Some(path) = entry.enclosed_name() else {
+			return Err(ArchiveError::UnsafePath(name));
+		};
+		let path = dir.join(path);
+
+		if entry.is_dir() {
+			std::fs::create_dir_all(&path).map_err(|e| ArchiveError::Entry {
+				name,
+				error: e.to_string(),
+			})?;
+			continue;
+		}
+
+		write_entry(&mut entry, &path, &name, &mut extracted)?;
+	}
+
+	Ok(())
+}
+
+fn list_zip(file: &Path) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+	let file = std::fs::File::open(file).map_err(|e| ArchiveError::Open(e.to_string()))?;
+	let mut archive = zip::ZipArchive::new(file).map_err(|e| ArchiveError::Open(e.to_string()))?;
+	check_zip(&mut archive)?;
+
+	let mut entries = Vec::with_capacity(archive.len());
+	for i in 0..archive.len() {
+		let entry = archive
+			.by_index_raw(i)
+			.map_err(|e| ArchiveError::Open(e.to_string()))?;
+		if entry.is_dir() {
+			continue;
+		}
+		entries.push(ArchiveEntry {
+			path: entry.name().replace('\\', "/"),
+			size: entry.size(),
+			compressed_size: Some(entry.compressed_size()),
+		});
+	}
+
+	Ok(entries)
+}
+
+fn sevenz_error(error: sevenz_rust::Error) -> ArchiveError {
+	match error {
+		sevenz_rust::Error::PasswordRequired | sevenz_rust::Error::MaybeBadPassword(_) => {
+			ArchiveError::Encrypted
+		}
+		e => ArchiveError::Open(e.to_string()),
+	}
+}
+
+fn open_7z(file: &Path) -> Result<sevenz_rust::SevenZReader<std::fs::File>, ArchiveError> {
+	sevenz_rust::SevenZReader::open(file, sevenz_rust::Password::empty()).map_err(sevenz_error)
+}
+
+fn check_7z(
+	reader: &sevenz_rust::SevenZReader<std::fs::File>,
+) -> Result<Vec<String>, ArchiveError> {
+	let entries = &reader.archive().files;
+	if entries.len() > MAX_ENTRIES {
+		return Err(ArchiveError::TooManyEntries);
+	}
+
+	let mut names = Vec::with_capacity(entries.len());
+	let mut declared = 0u64;
+	for entry in entries {
+		// Unix permissions are stored in the high half of the attributes when 0x8000 is set
+		let unix_symlink = entry.has_windows_attributes
+			&& entry.windows_attributes & 0x8000 != 0
+			&& (entry.windows_attributes >> 16) & 0o170000 == 0o120000;
+		let reparse_point = entry.has_windows_attributes && entry.windows_attributes & 0x400 != 0;
+		if unix_symlink || reparse_point {
+			return Err(ArchiveError::Symlink(entry.name.clone()));
+		}
+		if enclosed_path(&entry.name).is_none() {
+			return Err(ArchiveError::UnsafePath(entry.name.clone()));
+		}
+		declared = declared.saturating_add(entry.size);
+		names.push(entry.name.replace('\\', "/"));
+	}
+	if declared > MAX_EXTRACTED_SIZE {
+		return Err(ArchiveError::TooLarge);
+	}
+
+	Ok(names)
+}
+
+fn extract_7z(file: &Path, dir: &Path) -> Result<(), ArchiveError> {
+	let mut reader = open_7z(file)?;
+	check_7z(&reader)?;
+
+	let mut extracted = 0;
+	let mut error = None;
+	let result = reader.for_each_entries(|entry, entry_reader| {
+		let Some(path) = enclosed_path(&entry.name) else {
+			error = Some(ArchiveError::UnsafePath(entry.name.clone()));
+			return Ok(false);
+		};
+		let path = dir.join(path);
+
+		if entry.is_directory {
+			if let Err(e) = std::fs::create_dir_all(&path) {
+				error = Some(ArchiveError::Entry {
+					name: entry.name.clone(),
+					error: e.to_string(),
+				});
+				return Ok(false);
+			}
+			return Ok(true);
+		}
+
+		match write_entry(entry_reader, &path, &entry.name, &mut extracted) {
+			Ok(()) => Ok(true),
+			Err(e) => {
+				error = Some(e);
+				Ok(false)
+			}
+		}
+	});
+
+	if let Some(error) = error {
+		return Err(error);
+	}
+	result.map_err(sevenz_error)
+}
+
+/// Reads the sizes of every file from the 7z headers, without decompressing anything
+fn list_7z(file: &Path) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+	let reader = open_7z(file)?;
+	check_7z(&reader)?;
+
+	let archive = reader.archive();
+	let entries = archive
+		.files
+		.iter()
+		.enumerate()
+		.filter(|(_, entry)| !entry.is_directory)
+		.map(|(i, entry)| {
+			// Only a block holding a single file says how large that file is compressed
+			let alone = archive
+				.stream_map
+				.file_folder_index
+				.get(i)
+				.copied()
+				.flatten()
+				.and_then(|folder| archive.folders.get(folder))
+				.is_some_and(|folder| folder.num_unpack_sub_streams == 1);
+			ArchiveEntry {
+				path: entry.name.replace('\\', "/"),
+				size: entry.size,
+				compressed_size: alone.then_some(entry.compressed_size),
+			}
+		})
+		.collect();
+
+	Ok(entries)
+}
+
+/// There is no maintained pure Rust rar decoder, so rar still goes through unrar and the result is checked afterwards
+async fn extract_rar(file: &Path, dir: &Path) -> Result<(), ArchiveError> {
+	let output = tokio::process::Command::new("unrar")
+		.arg("x")
+		// Never prompt for a password, encrypted archives fail instead
+		.arg("-p-")
+		.arg(file)
+		.arg(format!("{}/", dir.display()))
+		.output()
+		.await
+		.map_err(|e| ArchiveError::Open(e.to_string()))?;
+
+	check_unrar_status(&output)?;
+
+	let mut entries = 0;
+	let mut extracted = 0u64;
+	for entry in walkdir::WalkDir::new(dir).min_depth(1) {
+		let entry = entry.map_err(|e| ArchiveError::Open(e.to_string()))?;
+		entries += 1;
+		if entries > MAX_ENTRIES {
+			return Err(ArchiveError::TooManyEntries);
+		}
+		if entry.path_is_symlink() {
+			return Err(ArchiveError::Symlink(entry.path().display().to_string()));
+		}
+		let metadata = entry
+			.metadata()
+			.map_err(|e| ArchiveError::Open(e.to_string()))?;
+		extracted += metadata.len();
+		if extracted > MAX_EXTRACTED_SIZE {
+			return Err(ArchiveError::TooLarge);
+		}
+	}
+
+	Ok(())
+}
+
+/// Maps unrar's exit codes, 11 is a wrong or missing password
+fn check_unrar_status(output: &std::process::Output) -> Result<(), ArchiveError> {
+	match output.status.code() {
+		Some(0) => Ok(()),
+		Some(11) => Err(ArchiveError::Encrypted),
+		_ => Err(ArchiveError::Open(
+			String::from_utf8_lossy(&output.stderr).trim().to_string(),
+		)),
+	}
+}
+
+async fn validate_rar(file: &Path) -> Result<Vec<String>, ArchiveError> {
+	// Testing decompresses every file in memory, which is the only way unrar notices encrypted file data
+	let output = tokio::process::Command::new("unrar")
+		.arg("t")
+		.arg("-p-")
+		.arg(file)
+		.output()
+		.await
+		.map_err(|e| ArchiveError::Open(e.to_string()))?;
+	check_unrar_status(&output)?;
+
+	let output = tokio::process::Command::new("unrar")
+		.arg("lb")
+		.arg("-p-")
+		.arg(file)
+		.output()
+		.await
+		.map_err(|e| ArchiveError::Open(e.to_string()))?;
+	check_unrar_status(&output)?;
+
+	let names = String::from_utf8_lossy(&output.stdout)
+		.lines()
+		.map(|name| name.replace('\\', "/"))
+		.collect::<Vec<_>>();
+	if names.len() > MAX_ENTRIES {
+		return Err(ArchiveError::TooManyEntries);
+	}
+	if let Some(name) = names.iter().find(|name| enclosed_path(name).is_none()) {
+		return Err(ArchiveError::UnsafePath(name.clone()));
+	}
+
+	Ok(names)
+}
+
+async fn list_rar(file: &Path) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+	// The technical listing prints a block of `Key: value` lines per entry, each starting with its name
+	let output = tokio::process::Command::new("unrar")
+		.arg("lt")
+		.arg("-p-")
+		.arg(file)
+		.output()
+		.await
+		.map_err(|e| ArchiveError::Open(e.to_string()))?;
+	check_unrar_status(&output)?;
+
+	let mut entries = Vec::new();
+	let mut entry: Option<ArchiveEntry> = None;
+	let mut is_file = true;
+	for line in String::from_utf8_lossy(&output.stdout).lines() {
+		let Some((key, value)) = line.trim().split_once(": ") else {
+			continue;
+		};
+		match key {
+			"Name" => {
+				if let Some(entry) = entry.take().filter(|_| is_file) {
+					entries.push(entry);
+				}
+				entry = Some(ArchiveEntry {
+					path: value.replace('\\', "/"),
+					size: 0,
+					compressed_size: None,
+				});
+				is_file = true;
+			}
+			"Type" => is_file = value == "File",
+			"Size" => {
+				if let Some(entry) = &mut entry {
+					entry.size = value.parse().unwrap_or(0);
+				}
+			}
+			"Packed size" => {
+				if let Some(entry) = &mut entry {
+					entry.compressed_size = value.parse().ok();
+				}
+			}
+			_ => {}
+		}
+	}
+	if let Some(entry) = entry.filter(|_| is_file) {
+		entries.push(entry);
+	}
+	if entries.len() > MAX_ENTRIES {
+		return Err(ArchiveError::TooManyEntries);
+	}
+
+	Ok(entries)
+}
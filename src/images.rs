@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::path::PathBuf;
+
+/// Largest image accepted for upload, matching Cloudflare Images
+pub const MAX_IMAGE_SIZE: usize = 10 * 1024 * 1024;
+/// Bounds of the thumbnail variant used by search cards, which are 16:9
+const THUMBNAIL_SIZE: (u32, u32) = (640, 360);
+
+/// Cloudflare's direct upload response, local uploads answer in the same shape so the upload page works with either
+#[derive(Serialize, Deserialize)]
+pub struct ImageUploadResult {
+	pub id: String,
+	pub variants: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImageUploadMessage {
+	pub message: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImageUploadResponse {
+	pub success: bool,
+	pub result: Option<ImageUploadResult>,
+	pub errors: Vec<ImageUploadMessage>,
+}
+
+#[axum::async_trait]
+pub trait ImageBackend: Send + Sync {
+	/// URL the browser posts the image to as a multipart `file` field
+	async fn upload_url(&self) -> Option<String>;
+
+	/// Whether an image URL belongs to this backend
+	fn is_hosted(&self, url: &str) -> bool;
+
+	async fn exists(&self, url: &str) -> bool {
+		reqwest::get(url)
+			.await
+			.is_ok_and(|response| response.status().is_success())
+	}
+
+	/// Stores an uploaded image, only for backends that serve images themselves
+	async fn store(&self, _data: &[u8]) -> Result<ImageUploadResult, String> {
+		Err(String::from(
+			"This image backend does not accept uploads directly",
+		))
+	}
+
+	/// Content type and data of a stored image variant
+	async fn load(&self, _id: &str, _variant: &str) -> Option<(&'static str, Vec<u8>)> {
+		None
+	}
+}
+
+pub struct CloudflareImages {
+	pub account_id: String,
+	pub token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CloudflareDirectUploadResult {
+	id: String,
+	#[serde(rename = "uploadURL")]
+	upload_url: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CloudflareDirectUpload {
+	success: bool,
+	result: CloudflareDirectUploadResult,
+}
+
+#[axum::async_trait]
+impl ImageBackend for CloudflareImages {
+	async fn upload_url(&self) -> Option<String> {
+		let cloudflare_url = format!(
+			"https://api.cloudflare.com/client/v4/accounts/{}/images/v2/direct_upload",
+			self.account_id
+		);
+
+		let response = reqwest::Client::new()
+			.post(&cloudflare_url)
+			.header(
+				axum::http::header::AUTHORIZATION.to_string(),
+				format!("Bearer {}", self.token),
+			)
+			.send()
+			.await
+			.ok()?;
+		if !response.status().is_success() {
+			return None;
+		}
+		let response = response.json::<CloudflareDirectUpload>().await.ok()?;
+		if response.success {
+			Some(response.result.upload_url)
+		} else {
+			None
+		}
+	}
+
+	fn is_hosted(&self, url: &str) -> bool {
+		url.starts_with("https://divamodarchive.com/cdn-cgi/imagedelivery")
+	}
+}
+
+pub struct LocalImages {
+	pub root: PathBuf,
+	/// Scheme and host images are linked from, without a trailing slash
+	pub public_url: String,
+}
+
+impl LocalImages {
+	fn variant_path(&self, id: &str, variant: &str, extension: &str) -> Option<PathBuf> {
+		if id.is_empty()
+			|| !id.chars().all(|c| c.is_ascii_hexdigit())
+			|| !["public", "thumbnail"].contains(&variant)
+		{
+			return None;
+		}
+		Some(self.root.join(id).join(format!("{variant}.{extension}")))
+	}
+}
+
+/// Re-encodes an image, which drops EXIF and any other metadata, keeping transparency if there is any
+fn encode(image: &image::DynamicImage) -> Result<(Vec<u8>, &'static str), String> {
+	let mut data = Vec::new();
+	if image.color().has_alpha() {
+		image
+			.write_with_encoder(image::codecs::png::PngEncoder::new(&mut data))
+			.map_err(|e| e.to_string())?;
+		Ok((data, "png"))
+	} else {
+		image
+			.to_rgb8()
+			.write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(
+				&mut data, 90,
+			))
+			.map_err(|e| e.to_string())?;
+		Ok((data, "jpg"))
+	}
+}
+
+#[axum::async_trait]
+impl ImageBackend for LocalImages {
+	async fn upload_url(&self) -> Option<String> {
+		Some(format!("{}/api/v1/images/upload", self.public_url))
+	}
+
+	fn is_hosted(&self, url: &str) -> bool {
+		url.starts_with(&format!("{}/images/", self.public_url))
+	}
+
+	async fn exists(&self, url: &str) -> bool {
+		let Some(path) = url.strip_prefix(&format!("{}/images/", self.public_url)) else {
+			return false;
+		};
+		let Some((id, variant)) = path.split_once('/') else {
+			return false;
+		};
+		self.load(id, variant).await.is_some()
+	}
+
+	async fn store(&self, data: &[u8]) -> Result<ImageUploadResult, String> {
+		if data.len() > MAX_IMAGE_SIZE {
+			return Err(String::from("Maximum size for images is 10MiB"));
+		}
+
+		let reader = image::ImageReader::new(std::io::Cursor::new(data))
+			.with_guessed_format()
+			.map_err(|e| e.to_string())?;
+		match reader.format() {
+			Some(image::ImageFormat::Png)
+			| Some(image::ImageFormat::Jpeg)
+			| Some(image::ImageFormat::WebP)
+			| Some(image::ImageFormat::Gif) => {}
+			_ => return Err(String::from("Images must be PNG, JPEG, WebP or GIF")),
+		}
+
+		let data = data.to_vec();
+		let (public, thumbnail) = tokio::task::spawn_blocking(move || {
+			let image = image::ImageReader::new(std::io::Cursor::new(data))
+				.with_guessed_format()
+				.map_err(|e| e.to_string())?
+				.decode()
+				.map_err(|e| e.to_string())?;
+			let public = encode(&image)?;
+			let thumbnail = encode(&image.thumbnail(THUMBNAIL_SIZE.0, THUMBNAIL_SIZE.1))?;
+			Ok::<_, String>((public, thumbnail))
+		})
+		.await
+		.map_err(|e| e.to_string())??;
+
+		// Named after the content so uploading the same image twice doesn't store it twice
+		let id = format!("{:x}", sha2::Sha256::digest(&public.0))[..32].to_string();
+		for (variant, (data, extension)) in [("public", public), ("thumbnail", thumbnail)] {
+			let Some(path) = self.variant_path(&id, variant, extension) else {
+				return Err(String::from("Invalid image id"));
+			};
+			if let Some(parent) = path.parent() {
+				tokio::fs::create_dir_all(parent)
+					.await
+					.map_err(|e| e.to_string())?;
+			}
+			tokio::fs::write(path, data)
+				.await
+				.map_err(|e| e.to_string())?;
+		}
+
+		Ok(ImageUploadResult {
+			variants: vec![
+				format!("{}/images/{id}/public", self.public_url),
+				format!("{}/images/{id}/thumbnail", self.public_url),
+			],
+			id,
+		})
+	}
+
+	async fn load(&self, id: &str, variant: &str) -> Option<(&'static str, Vec<u8>)> {
+		for (extension, content_type) in [("jpg", "image/jpeg"), ("png", "image/png")] {
+			let path = self.variant_path(id, variant, extension)?;
+			if let Ok(data) = tokio::fs::read(path).await {
+				return Some((content_type, data));
+			}
+		}
+		None
+	}
+}
@@ -0,0 +1,433 @@
+use crate::api::ids::{ExtractionError, ExtractionReport};
+use crate::AppState;
+use serde::{Deserialize, Serialize};
+
+/// Attempts before a job is left as failed
+pub const MAX_ATTEMPTS: i32 = 5;
+/// Delay before the first retry, doubled for every attempt after it
+pub const RETRY_DELAY: time::Duration = time::Duration::seconds(30);
+/// How often the worker checks for new jobs when the queue is empty
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+/// How often scheduled drafts are checked for
+const SCHEDULE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+/// Upload sessions without any progress for this long are dropped along with their partial files
+const UPLOAD_SESSION_EXPIRY: time::Duration = time::Duration::hours(24);
+/// How often download counts are pushed to the search index
+const DOWNLOAD_INDEX_INTERVAL: std::time::Duration = std::time::Duration::from_secs(300);
+/// Downloads within this long count towards the trending sort
+pub const TRENDING_WINDOW: time::Duration = time::Duration::days(7);
+
+#[repr(i32)]
+#[derive(PartialEq, Serialize, Deserialize, Clone, Copy)]
+pub enum JobStatus {
+	Pending = 0,
+	Running = 1,
+	Done = 2,
+	Failed = 3,
+}
+
+impl From<i32> for JobStatus {
+	fn from(value: i32) -> Self {
+		match value {
+			1 => Self::Running,
+			2 => Self::Done,
+			3 => Self::Failed,
+			_ => Self::Pending,
+		}
+	}
+}
+
+impl std::fmt::Display for JobStatus {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			JobStatus::Pending => "Queued",
+			JobStatus::Running => "Running",
+			JobStatus::Done => "Done",
+			JobStatus::Failed => "Failed",
+		})
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExtractionJob {
+	pub post_id: i32,
+	pub status: JobStatus,
+	pub attempts: i32,
+	pub error: Option<String>,
+	/// What the last successful run found in each file
+	pub report: Option<ExtractionReport>,
+	#[serde(with = "time::serde::rfc3339")]
+	pub run_at: time::OffsetDateTime,
+	#[serde(with = "time::serde::rfc3339")]
+	pub updated: time::OffsetDateTime,
+}
+
+impl ExtractionJob {
+	pub async fn get(post_id: i32, db: &sqlx::Pool<sqlx::Postgres>) -> Option<Self> {
+		let job = sqlx::query!(
+			"SELECT post_id, status, attempts, error, report, run_at, updated FROM extraction_jobs WHERE post_id = $1",
+			post_id
+		)
+		.fetch_one(db)
+		.await
+		.ok()?;
+
+		Some(Self {
+			post_id: job.post_id,
+			status: job.status.into(),
+			attempts: job.attempts,
+			error: job.error,
+			report: job
+				.report
+				.and_then(|report| serde_json::from_str(&report).ok()),
+			run_at: job.run_at.assume_offset(time::UtcOffset::UTC),
+			updated: job.updated.assume_offset(time::UtcOffset::UTC),
+		})
+	}
+}
+
+fn now() -> time::PrimitiveDateTime {
+	let now = time::OffsetDateTime::now_utc();
+	time::PrimitiveDateTime::new(now.date(), now.time())
+}
+
+/// Queues a post to have its PVs, modules and customize items extracted, restarting any previous job for it
+pub async fn enqueue_extraction(post_id: i32, db: &sqlx::Pool<sqlx::Postgres>) {
+	let now = now();
+	_ = sqlx::query!(
+		r#"
+		INSERT INTO extraction_jobs (post_id, status, attempts, error, run_at, updated)
+		VALUES ($1, $2, 0, NULL, $3, $3)
+		ON CONFLICT (post_id) DO UPDATE SET status = $2, attempts = 0, error = NULL, run_at = $3, updated = $3
+		"#,
+		post_id,
+		JobStatus::Pending as i32,
+		now
+	)
+	.execute(db)
+	.await;
+}
+
+/// Jobs that were running when the server stopped never finished, so they go back in the queue
+pub async fn recover_jobs(db: &sqlx::Pool<sqlx::Postgres>) {
+	_ = sqlx::query!(
+		"UPDATE extraction_jobs SET status = $1, updated = $2 WHERE status = $3",
+		JobStatus::Pending as i32,
+		now(),
+		JobStatus::Running as i32
+	)
+	.execute(db)
+	.await;
+}
+
+/// Takes the next due job, using SKIP LOCKED so several workers never pick the same post
+async fn claim_job(db: &sqlx::Pool<sqlx::Postgres>) -> Option<(i32, i32)> {
+	let now = now();
+	let job = sqlx::query!(
+		r#"
+		UPDATE extraction_jobs SET status = $1, attempts = attempts + 1, updated = $2
+		WHERE post_id = (
+			SELECT post_id FROM extraction_jobs
+			WHERE status = $3 AND run_at <= $2
+			ORDER BY run_at
+			LIMIT 1
+			FOR UPDATE SKIP LOCKED
+		)
+		RETURNING post_id, attempts
+		"#,
+		JobStatus::Running as i32,
+		now,
+		JobStatus::Pending as i32
+	)
+	.fetch_optional(db)
+	.await
+	.ok()??;
+
+	Some((job.post_id, job.attempts))
+}
+
+async fn finish_job(
+	post_id: i32,
+	attempts: i32,
+	result: Result<ExtractionReport, ExtractionError>,
+	state: &AppState,
+) {
+	let now = now();
+	let error = match result {
+		Ok(report) => {
+			_ = sqlx::query!(
+				"UPDATE extraction_jobs SET status = $2, error = NULL, report = $3, updated = $4 WHERE post_id = $1",
+				post_id,
+				JobStatus::Done as i32,
+				serde_json::to_string(&report).ok(),
+				now
+			)
+			.execute(&state.db)
+			.await;
+			return;
+		}
+		// Retrying can't bring a post back, the job goes with it
+		Err(ExtractionError::MissingPost) => {
+			_ = sqlx::query!("DELETE FROM extraction_jobs WHERE post_id = $1", post_id)
+				.execute(&state.db)
+				.await;
+			return;
+		}
+		Err(error) => error.to_string(),
+	};
+
+	let (status, run_at) = if attempts >= MAX_ATTEMPTS {
+		(JobStatus::Failed, now)
+	} else {
+		(
+			JobStatus::Pending,
+			now + RETRY_DELAY * 2i32.pow(attempts as u32 - 1),
+		)
+	};
+
+	_ = sqlx::query!(
+		"UPDATE extraction_jobs SET status = $2, error = $3, run_at = $4, updated = $5 WHERE post_id = $1",
+		post_id,
+		status as i32,
+		error,
+		run_at,
+		now
+	)
+	.execute(&state.db)
+	.await;
+}
+
+/// Queues every post matching the filters, both being None queues the whole archive
+pub async fn enqueue_extractions(
+	post_id: Option<i32>,
+	post_type: Option<i32>,
+	db: &sqlx::Pool<sqlx::Postgres>,
+) -> Vec<i32> {
+	let now = now();
+	sqlx::query!(
+		r#"
+		INSERT INTO extraction_jobs (post_id, status, attempts, error, run_at, updated)
+		SELECT id, $3, 0, NULL, $4, $4 FROM posts
+		WHERE ($1::int IS NULL OR id = $1) AND ($2::int IS NULL OR type = $2) AND NOT draft
+		ON CONFLICT (post_id) DO UPDATE SET status = $3, attempts = 0, error = NULL, run_at = $4, updated = $4
+		RETURNING post_id
+		"#,
+		post_id,
+		post_type,
+		JobStatus::Pending as i32,
+		now
+	)
+	.fetch_all(db)
+	.await
+	.map(|jobs| jobs.into_iter().map(|job| job.post_id).collect())
+	.unwrap_or_default()
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ExtractionProgress {
+	pub pending: i64,
+	pub running: i64,
+	pub done: i64,
+	pub failed: i64,
+	pub failures: Vec<ExtractionJob>,
+}
+
+impl ExtractionProgress {
+	pub async fn get(db: &sqlx::Pool<sqlx::Postgres>) -> Self {
+		let Ok(counts) = sqlx::query!(
+			r#"SELECT status, COUNT(*) as "count!" FROM extraction_jobs GROUP BY status"#
+		)
+		.fetch_all(db)
+		.await
+		else {
+			return Self::default();
+		};
+
+		let mut progress = Self::default();
+		for count in counts {
+			match JobStatus::from(count.status) {
+				JobStatus::Pending => progress.pending = count.count,
+				JobStatus::Running => progress.running = count.count,
+				JobStatus::Done => progress.done = count.count,
+				JobStatus::Failed => progress.failed = count.count,
+			}
+		}
+
+		let failed = sqlx::query!(
+			"SELECT post_id FROM extraction_jobs WHERE status = $1 ORDER BY updated DESC",
+			JobStatus::Failed as i32
+		)
+		.fetch_all(db)
+		.await
+		.unwrap_or_default();
+		for job in failed {
+			if let Some(job) = ExtractionJob::get(job.post_id, db).await {
+				progress.failures.push(job);
+			}
+		}
+
+		progress
+	}
+}
+
+/// Clears and re-extracts a claimed post, recording the outcome on its job
+async fn run_job(post_id: i32, attempts: i32, state: &AppState) -> Result<(), String> {
+	crate::api::ids::clear_post_data(post_id, state).await;
+
+	// Run in its own task so a panic in the parsers fails the job instead of killing the worker
+	let result = tokio::spawn(crate::api::ids::extract_post_data(post_id, state.clone())).await;
+	let result = match result {
+		Ok(result) => result,
+		Err(e) => Err(ExtractionError::Transient(format!(
+			"Extraction panicked: {e}"
+		))),
+	};
+	let error = result.as_ref().err().map(|error| error.to_string());
+	let missing = matches!(result, Err(ExtractionError::MissingPost));
+
+	finish_job(post_id, attempts, result, state).await;
+	if !missing {
+		// Picks up the mod configs that were just recorded
+		crate::api::posts::update_search_index(post_id, state).await;
+	}
+
+	match error {
+		Some(error) => Err(error),
+		None => Ok(()),
+	}
+}
+
+/// Runs queued extraction jobs forever, one at a time
+pub async fn run_worker(state: AppState) {
+	loop {
+		let Some((post_id, attempts)) = claim_job(&state.db).await else {
+			tokio::time::sleep(POLL_INTERVAL).await;
+			continue;
+		};
+
+		_ = run_job(post_id, attempts, &state).await;
+	}
+}
+
+/// Publishes drafts once their scheduled time has passed
+pub async fn run_scheduler(state: AppState) {
+	loop {
+		let due = sqlx::query!(
+			"SELECT id FROM posts WHERE draft AND publish_at <= $1",
+			now()
+		)
+		.fetch_all(&state.db)
+		.await
+		.unwrap_or_default();
+
+		for post in due {
+			crate::api::posts::publish_post(post.id, &state).await;
+		}
+
+		let expired = sqlx::query!(
+			"SELECT id, filepaths FROM upload_sessions WHERE time <= $1",
+			now() - UPLOAD_SESSION_EXPIRY
+		)
+		.fetch_all(&state.db)
+		.await
+		.unwrap_or_default();
+
+		for session in expired {
+			crate::api::posts::discard_upload(session.id, &session.filepaths, &state).await;
+		}
+
+		tokio::time::sleep(SCHEDULE_INTERVAL).await;
+	}
+}
+
+struct DownloadStats {
+	id: i32,
+	weekly_downloads: i64,
+}
+
+/// Whole posts index document rather than just the counts, so a post missing from the index is added in full instead of as a stub search can't read
+#[derive(Serialize)]
+struct TrendingPost<'a> {
+	#[serde(flatten)]
+	post: crate::api::posts::MeilisearchPost<'a>,
+	weekly_downloads: i64,
+}
+
+/// Updates download counts in the search index in batches, rather than once per download
+pub async fn run_download_indexer(state: AppState) {
+	loop {
+		// Posts that were indexed with downloads are included too, so they drop to zero once their last ones leave the window, however long the indexer was down for
+		let stats = sqlx::query_as!(
+			DownloadStats,
+			r#"
+			SELECT p.id, COUNT(e.post_id) AS "weekly_downloads!"
+			FROM posts p
+			LEFT JOIN download_events e ON e.post_id = p.id AND e.time >= $1 AND e.counted
+			WHERE NOT p.draft
+			GROUP BY p.id
+			HAVING COUNT(e.post_id) > 0 OR p.indexed_weekly_downloads > 0
+			"#,
+			now() - TRENDING_WINDOW
+		)
+		.fetch_all(&state.db)
+		.await
+		.unwrap_or_default();
+
+		let mut posts = Vec::with_capacity(stats.len());
+		for stats in &stats {
+			if let Some(post) = crate::models::Post::get_short(stats.id, &state.db).await {
+				if !post.draft {
+					posts.push((post, stats.weekly_downloads));
+				}
+			}
+		}
+		let documents = posts
+			.iter()
+			.map(|(post, weekly_downloads)| TrendingPost {
+				post: crate::api::posts::MeilisearchPost::from(post),
+				weekly_downloads: *weekly_downloads,
+			})
+			.collect::<Vec<_>>();
+
+		if !documents.is_empty()
+			&& state
+				.meilisearch
+				.index("posts")
+				.add_or_update(&documents, Some("id"))
+				.await
+				.is_ok()
+		{
+			let ids = posts.iter().map(|(post, _)| post.id).collect::<Vec<_>>();
+			let weekly_downloads = posts
+				.iter()
+				.map(|(_, weekly_downloads)| *weekly_downloads)
+				.collect::<Vec<_>>();
+			_ = sqlx::query!(
+				r#"
+				UPDATE posts p SET indexed_weekly_downloads = s.weekly_downloads
+				FROM UNNEST($1::int[], $2::bigint[]) AS s(id, weekly_downloads)
+				WHERE p.id = s.id
+				"#,
+				&ids,
+				&weekly_downloads
+			)
+			.execute(&state.db)
+			.await;
+		}
+
+		tokio::time::sleep(DOWNLOAD_INDEX_INTERVAL).await;
+	}
+}
+
+/// Works through every job that is currently due and returns, used by the reextract command
+pub async fn drain_queue(total: usize, state: &AppState) {
+	let mut finished = 0;
+	while let Some((post_id, attempts)) = claim_job(&state.db).await {
+		let result = run_job(post_id, attempts, state).await;
+		finished += 1;
+		match result {
+			Ok(()) => println!("[{finished}/{total}] Post {post_id}: done"),
+			Err(e) => println!("[{finished}/{total}] Post {post_id}: {e} (attempt {attempts})"),
+		}
+	}
+}
@@ -1,12 +1,19 @@
 pub mod api;
+pub mod archive;
+pub mod images;
+pub mod jobs;
 pub mod models;
 pub mod sitemap;
+pub mod storage;
 pub mod web;
 
 use axum::{http::HeaderMap, routing::*, Router};
+use images::*;
 use meilisearch_sdk::client::*;
 use models::*;
 use sqlx::postgres::PgPoolOptions;
+use std::sync::Arc;
+use storage::*;
 
 #[derive(Clone)]
 pub struct Config {
@@ -14,9 +21,21 @@ pub struct Config {
 	pub encoding_key: jsonwebtoken::EncodingKey,
 	pub discord_id: String,
 	pub discord_secret: String,
-	pub cloudflare_image_token: String,
-	pub cloudflare_account_id: String,
 	pub admins: Vec<i64>,
+	/// Mixed into client fingerprints so they can't be reversed by hashing every IP
+	pub fingerprint_key: String,
+	/// Repeated downloads of a post by the same client within this window only count once
+	pub download_window: time::Duration,
+	/// Proxy whose headers can be trusted for the client's IP, without one the socket address is used
+	pub trusted_proxy: Option<TrustedProxy>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TrustedProxy {
+	/// Takes the IP from `CF-Connecting-IP`
+	Cloudflare,
+	/// Takes the last IP in `X-Forwarded-For`, which is the one the proxy added
+	Forwarded,
 }
 
 #[derive(Clone)]
@@ -24,6 +43,8 @@ pub struct AppState {
 	pub config: Config,
 	pub db: sqlx::Pool<sqlx::Postgres>,
 	pub meilisearch: Client,
+	pub storage: Arc<dyn Storage>,
+	pub images: Arc<dyn ImageBackend>,
 }
 
 #[tokio::main]
@@ -36,22 +57,34 @@ async fn main() {
 		.connect(&database_url)
 		.await
 		.expect("Could not connect to database");
-	sqlx::migrate!()
-		.run(&db)
-		.await
-		.expect("Unable to run migrations");
 
 	let secret_key = std::env::var("SECRET_KEY").expect("SECRET_KEY must exist");
 	let decoding_key = jsonwebtoken::DecodingKey::from_secret(secret_key.as_bytes());
 	let encoding_key = jsonwebtoken::EncodingKey::from_secret(secret_key.as_bytes());
 
+	let fingerprint_key = std::env::var("FINGERPRINT_KEY").expect("FINGERPRINT_KEY must exist");
+
 	let discord_id = std::env::var("DISCORD_ID").expect("DISCORD_ID must exist");
 	let discord_secret = std::env::var("DISCORD_SECRET").expect("DISCORD_SECRET must exist");
 
-	let cloudflare_image_token =
-		std::env::var("CLOUDFLARE_IMAGE_TOKEN").expect("CLOUDFLARE_IMAGE_TOKEN must exist");
-	let cloudflare_account_id =
-		std::env::var("CLOUDFLARE_ACCOUNT_ID").expect("CLOUDFLARE_ACCOUNT_ID must exist");
+	let images: Arc<dyn ImageBackend> = match std::env::var("IMAGE_BACKEND").as_deref() {
+		Ok("local") => Arc::new(LocalImages {
+			root: std::path::PathBuf::from(
+				std::env::var("IMAGE_PATH").expect("IMAGE_PATH must exist"),
+			),
+			public_url: std::env::var("PUBLIC_URL")
+				.unwrap_or(String::from("https://divamodarchive.com"))
+				.trim_end_matches('/')
+				.to_string(),
+		}),
+		Ok("cloudflare") | Err(_) => Arc::new(CloudflareImages {
+			account_id: std::env::var("CLOUDFLARE_ACCOUNT_ID")
+				.expect("CLOUDFLARE_ACCOUNT_ID must exist"),
+			token: std::env::var("CLOUDFLARE_IMAGE_TOKEN")
+				.expect("CLOUDFLARE_IMAGE_TOKEN must exist"),
+		}),
+		Ok(backend) => panic!("Unknown IMAGE_BACKEND {backend}"),
+	};
 
 	let admins = std::env::var("ADMIN_IDS")
 		.expect("ADMIN_IDS must exist")
@@ -61,38 +94,125 @@ async fn main() {
 
 	let meilisearch_url = std::env::var("MEILISEARCH_URL").expect("MEILISEARCH_URL must exist");
 
+	let storage_path = match std::env::var("STORAGE_BACKEND").as_deref() {
+		Ok("local") => Some(std::path::PathBuf::from(
+			std::env::var("STORAGE_PATH").expect("STORAGE_PATH must exist"),
+		)),
+		Ok("pixeldrain") | Err(_) => None,
+		Ok(backend) => panic!("Unknown STORAGE_BACKEND {backend}"),
+	};
+	let storage: Arc<dyn Storage> = if let Some(root) = &storage_path {
+		Arc::new(LocalStorage { root: root.clone() })
+	} else {
+		Arc::new(PixeldrainStorage::default())
+	};
+
+	let download_window = time::Duration::hours(
+		std::env::var("DOWNLOAD_WINDOW_HOURS")
+			.ok()
+			.and_then(|hours| hours.parse().ok())
+			.unwrap_or(24),
+	);
+
+	let trusted_proxy = match std::env::var("TRUSTED_PROXY").as_deref() {
+		Ok("cloudflare") => Some(TrustedProxy::Cloudflare),
+		Ok("forwarded") => Some(TrustedProxy::Forwarded),
+		Ok("none") | Err(_) => None,
+		Ok(proxy) => panic!("Unknown TRUSTED_PROXY {proxy}"),
+	};
+
 	let config = Config {
 		decoding_key,
 		encoding_key,
 		discord_id,
 		discord_secret,
-		cloudflare_image_token,
-		cloudflare_account_id,
 		admins,
+		fingerprint_key,
+		download_window,
+		trusted_proxy,
 	};
 
 	let client = meilisearch_sdk::client::Client::new(meilisearch_url, None::<&str>).unwrap();
 
-	let meilisearch_posts = client.index("posts");
-	let meilisearch_pvs = client.index("pvs");
-	let meilisearch_modules = client.index("modules");
-	let meilisearch_customize = client.index("cstm_items");
+	let state = AppState {
+		config,
+		db,
+		meilisearch: client,
+		storage,
+		images,
+	};
+
+	// Commands run next to a live server, so they skip everything it already did on startup
+	let args = std::env::args().skip(1).collect::<Vec<_>>();
+	if let Some(command) = args.first() {
+		match command.as_str() {
+			"reextract" => reextract(&args[1..], &state).await,
+			_ => panic!("Unknown command {command}, expected reextract"),
+		}
+		return;
+	}
+
+	sqlx::migrate!()
+		.run(&state.db)
+		.await
+		.expect("Unable to run migrations");
+
+	let meilisearch_posts = state.meilisearch.index("posts");
+	let meilisearch_pvs = state.meilisearch.index("pvs");
+	let meilisearch_modules = state.meilisearch.index("modules");
+	let meilisearch_customize = state.meilisearch.index("cstm_items");
 
 	meilisearch_posts
-		.set_searchable_attributes(&["name", "text", "authors.name"])
+		.set_searchable_attributes(&[
+			"name",
+			"text",
+			"authors.name",
+			"tags",
+			"configs.name",
+			"configs.author",
+			"configs.description",
+			"configs.dll",
+			"dlls.name",
+			"dlls.product_name",
+			"dlls.file_description",
+		])
 		.await
 		.unwrap();
 	meilisearch_posts
-		.set_filterable_attributes(&["post_type", "id"])
+		.set_filterable_attributes(&[
+			"post_type",
+			"id",
+			"authors.name",
+			"timestamp",
+			"tags",
+			"games",
+			"min_dml_version_code",
+			"dlls.name",
+			"dlls.sha256",
+		])
 		.await
 		.unwrap();
 	meilisearch_posts
-		.set_sortable_attributes(&["download_count", "like_count", "time"])
+		.set_sortable_attributes(&["download_count", "like_count", "time", "weekly_downloads"])
 		.await
 		.unwrap();
 
 	meilisearch_pvs
-		.set_filterable_attributes(&["post", "pv_id"])
+		.set_filterable_attributes(&[
+			"post",
+			"pv_id",
+			"charts.difficulty",
+			"charts.edition",
+			"charts.attributes",
+			"performers.chara",
+			"bpm",
+			"date",
+			"stars.easy",
+			"stars.normal",
+			"stars.hard",
+			"stars.extreme",
+			"stars.extra_extreme",
+		])
 		.await
 		.unwrap();
 	meilisearch_pvs
@@ -102,16 +222,27 @@ async fn main() {
 			"song_name_en",
 			"song_info",
 			"song_info_en",
+			"charts.script_file_name",
+			"performers.chara",
 		])
 		.await
 		.unwrap();
 	meilisearch_pvs
-		.set_sortable_attributes(&["pv_id"])
+		.set_sortable_attributes(&[
+			"pv_id",
+			"bpm",
+			"date",
+			"stars.easy",
+			"stars.normal",
+			"stars.hard",
+			"stars.extreme",
+			"stars.extra_extreme",
+		])
 		.await
 		.unwrap();
 
 	meilisearch_modules
-		.set_filterable_attributes(&["post_id", "module_id"])
+		.set_filterable_attributes(&["post_id", "module_id", "chara"])
 		.await
 		.unwrap();
 	meilisearch_modules
@@ -137,7 +268,7 @@ async fn main() {
 		.unwrap();
 
 	meilisearch_customize
-		.set_filterable_attributes(&["post_id", "customize_item_id"])
+		.set_filterable_attributes(&["post_id", "customize_item_id", "chara"])
 		.await
 		.unwrap();
 	meilisearch_customize
@@ -163,32 +294,37 @@ async fn main() {
 		.await
 		.unwrap();
 
-	let posts = sqlx::query!("SELECT id FROM posts ORDER BY time DESC")
-		.fetch_all(&db)
+	let posts = sqlx::query!("SELECT id FROM posts WHERE NOT draft ORDER BY time DESC")
+		.fetch_all(&state.db)
 		.await;
 
 	if let Ok(posts) = posts {
 		let mut vec = Vec::with_capacity(posts.len());
 		for post in &posts {
-			let Some(post) = Post::get_short(post.id, &db).await else {
+			let Some(post) = Post::get_short(post.id, &state.db).await else {
 				continue;
 			};
 			vec.push(post);
 		}
+		let vec = vec
+			.iter()
+			.map(api::posts::MeilisearchPost::from)
+			.collect::<Vec<_>>();
 		meilisearch_posts.add_or_update(&vec, None).await.unwrap();
 	}
 
-	let state = AppState {
-		config,
-		db,
-		meilisearch: client,
-	};
-
 	api::ids::optimise_reservations(api::ids::ReservationType::Song, &state).await;
 	api::ids::optimise_reservations(api::ids::ReservationType::Module, &state).await;
 	api::ids::optimise_reservations(api::ids::ReservationType::CstmItem, &state).await;
 
-	let router = Router::new()
+	// Only the server recovers jobs, a command doing so would take over the running worker's jobs
+	jobs::recover_jobs(&state.db).await;
+
+	tokio::spawn(jobs::run_worker(state.clone()));
+	tokio::spawn(jobs::run_scheduler(state.clone()));
+	tokio::spawn(jobs::run_download_indexer(state.clone()));
+
+	let mut router = Router::new()
 		.route("/robots.txt", get(robots))
 		.route("/favicon.ico", get(favicon))
 		.route("/dma_black.png", get(dma_black))
@@ -206,10 +342,50 @@ async fn main() {
 		.with_state(state.clone())
 		.merge(web::route(state.clone()))
 		.merge(api::route(state.clone()));
+	if let Some(root) = storage_path {
+		router = router.nest_service("/files", tower_http::services::ServeDir::new(root));
+	}
 	let listener = tokio::net::TcpListener::bind("0.0.0.0:7001")
 		.await
 		.expect("Unable to bind on port {}");
-	axum::serve(listener, router).await.unwrap();
+	axum::serve(
+		listener,
+		router.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+	)
+	.await
+	.unwrap();
+}
+
+/// `reextract all`, `reextract post <id>` or `reextract type <plugin|module|song|cover|ui|other>`
+async fn reextract(args: &[String], state: &AppState) {
+	let usage = "Usage: reextract all | post <id> | type <post type>";
+	let (post_id, post_type) = match args {
+		[all] if all == "all" => (None, None),
+		[post, id] if post == "post" => (Some(id.parse::<i32>().expect(usage)), None),
+		[post_type, name] if post_type == "type" => {
+			let post_type = match name.to_lowercase().as_str() {
+				"plugin" => PostType::Plugin,
+				"module" => PostType::Module,
+				"song" => PostType::Song,
+				"cover" => PostType::Cover,
+				"ui" => PostType::Ui,
+				"other" => PostType::Other,
+				_ => PostType::from(name.parse::<i32>().expect(usage)),
+			};
+			(None, Some(post_type as i32))
+		}
+		_ => panic!("{usage}"),
+	};
+
+	let queued = jobs::enqueue_extractions(post_id, post_type, &state.db).await;
+	println!("Queued {} posts", queued.len());
+	jobs::drain_queue(queued.len(), state).await;
+
+	let progress = jobs::ExtractionProgress::get(&state.db).await;
+	println!(
+		"{} done, {} failed, {} waiting to retry",
+		progress.done, progress.failed, progress.pending
+	);
 }
 
 pub async fn robots() -> &'static str {
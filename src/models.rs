@@ -62,10 +62,46 @@ pub enum PostType {
 	Module = 1,
 	Song = 2,
 	Cover = 3,
+	#[serde(alias = "UI")]
 	Ui = 4,
 	Other = 5,
 }
 
+/// Game a post targets
+#[repr(i32)]
+#[derive(PartialEq, Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum Game {
+	/// Mega Mix+ on Steam, with DivaModLoader
+	MegaMixPlus = 0,
+	/// Future Tone on PS4
+	FutureTone = 1,
+	/// Mega Mix on Switch
+	MegaMix = 2,
+}
+
+impl Game {
+	pub const ALL: [Game; 3] = [Game::MegaMixPlus, Game::FutureTone, Game::MegaMix];
+
+	pub fn from_i32(value: i32) -> Option<Self> {
+		match value {
+			0 => Some(Self::MegaMixPlus),
+			1 => Some(Self::FutureTone),
+			2 => Some(Self::MegaMix),
+			_ => None,
+		}
+	}
+}
+
+impl std::fmt::Display for Game {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Game::MegaMixPlus => "Mega Mix+ (Steam)",
+			Game::FutureTone => "Future Tone (PS4)",
+			Game::MegaMix => "Mega Mix (Switch)",
+		})
+	}
+}
+
 impl From<i32> for PostType {
 	fn from(value: i32) -> Self {
 		match value {
@@ -110,6 +146,25 @@ pub struct Post {
 	pub comments: Option<Comments>,
 	#[serde(rename = "file_names")]
 	pub local_files: Vec<String>,
+	/// Lowercase hex SHA-256 of each file, empty for posts uploaded before hashes were recorded
+	pub file_hashes: Vec<String>,
+	pub file_sizes: Vec<i64>,
+	/// Drafts are only visible to their authors until published
+	pub draft: bool,
+	#[serde(with = "time::serde::rfc3339::option")]
+	pub publish_at: Option<time::OffsetDateTime>,
+	/// DivaModLoader config.toml files found while extracting
+	pub configs: Vec<ModConfig>,
+	/// DLLs loaded by those configs
+	pub dlls: Vec<PluginDll>,
+	/// Normalised free-form tags set by the authors
+	pub tags: Vec<String>,
+	/// Games the post works with, set by the authors or otherwise inferred while extracting
+	pub games: Vec<Game>,
+	/// Whether games came from the extractor rather than the authors
+	pub games_inferred: bool,
+	/// Oldest DivaModLoader release the post works with
+	pub min_dml_version: Option<String>,
 }
 
 impl Clone for Post {
@@ -128,6 +183,16 @@ impl Clone for Post {
 			dependencies: self.dependencies.clone(),
 			comments: None,
 			local_files: self.local_files.clone(),
+			file_hashes: self.file_hashes.clone(),
+			file_sizes: self.file_sizes.clone(),
+			draft: self.draft,
+			publish_at: self.publish_at,
+			configs: self.configs.clone(),
+			dlls: self.dlls.clone(),
+			tags: self.tags.clone(),
+			games: self.games.clone(),
+			games_inferred: self.games_inferred,
+			min_dml_version: self.min_dml_version.clone(),
 		}
 	}
 }
@@ -215,7 +280,7 @@ impl Post {
 	pub async fn get_full(id: i32, db: &sqlx::Pool<sqlx::Postgres>) -> Option<Self> {
 		let post = sqlx::query!(
 			r#"
-			SELECT p.id, p.name, p.text, p.images, p.files, p.time, p.type as post_type, p.download_count, p.local_files, like_count.like_count
+			SELECT p.id, p.name, p.text, p.images, p.files, p.time, p.type as post_type, p.download_count, p.local_files, p.file_hashes, p.file_sizes, p.draft, p.publish_at, ARRAY(SELECT tag FROM post_tags t WHERE t.post_id = p.id ORDER BY tag) AS "tags!", COALESCE(p.games, p.inferred_games) AS "games!", p.games IS NULL AS "games_inferred!", p.min_dml_version, like_count.like_count
 			FROM posts p
 			LEFT JOIN post_comments c ON p.id = c.post_id
 			LEFT JOIN (SELECT post_id, COUNT(*) as like_count FROM liked_posts GROUP BY post_id) AS like_count ON p.id = like_count.post_id
@@ -243,13 +308,13 @@ impl Post {
 
 		let dependencies = sqlx::query!(
 			r#"
-			SELECT p.id, p.name, p.text, p.images, p.files, p.time, p.type as post_type, p.download_count, p.local_files, COALESCE(like_count.count, 0) AS "like_count!"
+			SELECT p.id, p.name, p.text, p.images, p.files, p.time, p.type as post_type, p.download_count, p.local_files, p.file_hashes, p.file_sizes, p.draft, p.publish_at, ARRAY(SELECT tag FROM post_tags t WHERE t.post_id = p.id ORDER BY tag) AS "tags!", COALESCE(p.games, p.inferred_games) AS "games!", p.games IS NULL AS "games_inferred!", p.min_dml_version, COALESCE(like_count.count, 0) AS "like_count!"
 			FROM post_dependencies pd
 			LEFT JOIN posts p ON pd.dependency_id = p.id
 			LEFT JOIN (SELECT post_id, COUNT(*) as count FROM liked_posts GROUP BY post_id) AS like_count ON p.id = like_count.post_id
 			LEFT JOIN post_authors pa ON pa.post_id = p.id
 			LEFT JOIN users u ON pa.user_id = u.id
-			WHERE pd.post_id = $1
+			WHERE pd.post_id = $1 AND NOT p.draft
 			"#,
 			id
 		)
@@ -289,6 +354,18 @@ impl Post {
 				dependencies: None,
 				comments: None,
 				local_files: dep.local_files,
+				file_hashes: dep.file_hashes,
+				file_sizes: dep.file_sizes,
+				draft: dep.draft,
+				publish_at: dep
+					.publish_at
+					.map(|time| time.assume_offset(time::UtcOffset::UTC)),
+				configs: Vec::new(),
+				dlls: Vec::new(),
+				tags: dep.tags,
+				games: dep.games.into_iter().filter_map(Game::from_i32).collect(),
+				games_inferred: dep.games_inferred,
+				min_dml_version: dep.min_dml_version,
 			});
 		}
 
@@ -386,13 +463,25 @@ impl Post {
 			dependencies: Some(deps),
 			comments: Some(comments),
 			local_files: post.local_files,
+			file_hashes: post.file_hashes,
+			file_sizes: post.file_sizes,
+			draft: post.draft,
+			publish_at: post
+				.publish_at
+				.map(|time| time.assume_offset(time::UtcOffset::UTC)),
+			configs: ModConfig::list(id, db).await,
+			dlls: PluginDll::list(id, db).await,
+			tags: post.tags,
+			games: post.games.into_iter().filter_map(Game::from_i32).collect(),
+			games_inferred: post.games_inferred,
+			min_dml_version: post.min_dml_version,
 		})
 	}
 
 	pub async fn get_short(id: i32, db: &sqlx::Pool<sqlx::Postgres>) -> Option<Self> {
 		let post = sqlx::query!(
 			r#"
-			SELECT p.id, p.name, p.text, p.images, p.files, p.time, p.type as post_type, p.download_count, p.local_files, like_count.like_count
+			SELECT p.id, p.name, p.text, p.images, p.files, p.time, p.type as post_type, p.download_count, p.local_files, p.file_hashes, p.file_sizes, p.draft, p.publish_at, ARRAY(SELECT tag FROM post_tags t WHERE t.post_id = p.id ORDER BY tag) AS "tags!", COALESCE(p.games, p.inferred_games) AS "games!", p.games IS NULL AS "games_inferred!", p.min_dml_version, like_count.like_count
 			FROM posts p
 			LEFT JOIN post_comments c ON p.id = c.post_id
 			LEFT JOIN (SELECT post_id, COUNT(*) as like_count FROM liked_posts GROUP BY post_id) AS like_count ON p.id = like_count.post_id
@@ -432,7 +521,176 @@ impl Post {
 			dependencies: None,
 			comments: None,
 			local_files: post.local_files,
+			file_hashes: post.file_hashes,
+			file_sizes: post.file_sizes,
+			draft: post.draft,
+			publish_at: post
+				.publish_at
+				.map(|time| time.assume_offset(time::UtcOffset::UTC)),
+			configs: ModConfig::list(id, db).await,
+			dlls: PluginDll::list(id, db).await,
+			tags: post.tags,
+			games: post.games.into_iter().filter_map(Game::from_i32).collect(),
+			games_inferred: post.games_inferred,
+			min_dml_version: post.min_dml_version,
+		})
+	}
+
+	pub fn is_visible_to(&self, user: Option<&User>, config: &Config) -> bool {
+		if !self.draft {
+			return true;
+		}
+		let Some(user) = user else {
+			return false;
+		};
+		self.authors.iter().any(|author| author.id == user.id) || user.is_admin(config)
+	}
+}
+
+/// A set of files a post has been published with, the newest one matches the post itself
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PostVersion {
+	pub version: i32,
+	pub changelog: Option<String>,
+	pub files: Vec<String>,
+	#[serde(rename = "file_names")]
+	pub local_files: Vec<String>,
+	pub file_hashes: Vec<String>,
+	pub file_sizes: Vec<i64>,
+	#[serde(with = "time::serde::rfc3339")]
+	pub time: time::OffsetDateTime,
+}
+
+/// Metadata from a config.toml inside a post
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ModConfig {
+	/// Archive name followed by the path of the config.toml inside it
+	pub path: String,
+	pub name: Option<String>,
+	pub author: Option<String>,
+	pub version: Option<String>,
+	pub description: Option<String>,
+	pub dll: Vec<String>,
+	pub enabled: bool,
+}
+
+impl ModConfig {
+	pub async fn list(post_id: i32, db: &sqlx::Pool<sqlx::Postgres>) -> Vec<Self> {
+		sqlx::query_as!(
+			ModConfig,
+			"SELECT path, name, author, version, description, dll, enabled FROM mod_configs WHERE post_id = $1 ORDER BY path",
+			post_id
+		)
+		.fetch_all(db)
+		.await
+		.unwrap_or_default()
+	}
+}
+
+/// A DLL a config.toml loads, with what its version resource says about it
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PluginDll {
+	/// Archive name followed by the path of the DLL inside it
+	pub path: String,
+	pub name: String,
+	pub sha256: String,
+	pub file_version: Option<String>,
+	pub product_version: Option<String>,
+	pub product_name: Option<String>,
+	pub company_name: Option<String>,
+	pub file_description: Option<String>,
+}
+
+impl PluginDll {
+	pub async fn list(post_id: i32, db: &sqlx::Pool<sqlx::Postgres>) -> Vec<Self> {
+		sqlx::query_as!(
+			PluginDll,
+			"SELECT path, name, sha256, file_version, product_version, product_name, company_name, file_description FROM post_dlls WHERE post_id = $1 ORDER BY path",
+			post_id
+		)
+		.fetch_all(db)
+		.await
+		.unwrap_or_default()
+	}
+}
+
+/// A file inside one of the archives of a post
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ArchiveFile {
+	pub archive: String,
+	pub path: String,
+	pub size: i64,
+	pub compressed_size: Option<i64>,
+}
+
+impl ArchiveFile {
+	pub async fn list(post_id: i32, db: &sqlx::Pool<sqlx::Postgres>) -> Vec<Self> {
+		sqlx::query_as!(
+			ArchiveFile,
+			"SELECT archive, path, size, compressed_size FROM archive_files WHERE post_id = $1 ORDER BY archive, path",
+			post_id
+		)
+		.fetch_all(db)
+		.await
+		.unwrap_or_default()
+	}
+}
+
+/// What an author wrote about a version when uploading it
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Changelog {
+	pub version: i32,
+	pub text: String,
+	#[serde(with = "time::serde::rfc3339")]
+	pub time: time::OffsetDateTime,
+}
+
+impl Changelog {
+	/// Changelogs of a post, both from uploads and from metadata edits, newest first
+	pub async fn list(post_id: i32, db: &sqlx::Pool<sqlx::Postgres>) -> Vec<Self> {
+		sqlx::query!(
+			r#"
+			SELECT version AS "version!", changelog AS "changelog!", time AS "time!" FROM post_versions WHERE post_id = $1 AND changelog IS NOT NULL
+			UNION ALL
+			SELECT version, text, time FROM post_changelogs WHERE post_id = $1
+			ORDER BY 3 DESC
+			"#,
+			post_id
+		)
+		.fetch_all(db)
+		.await
+		.unwrap_or_default()
+		.into_iter()
+		.map(|changelog| Changelog {
+			version: changelog.version,
+			text: changelog.changelog,
+			time: changelog.time.assume_offset(time::UtcOffset::UTC),
+		})
+		.collect()
+	}
+}
+
+impl PostVersion {
+	/// Every version of a post, newest first
+	pub async fn list(post_id: i32, db: &sqlx::Pool<sqlx::Postgres>) -> Vec<Self> {
+		sqlx::query!(
+			"SELECT version, changelog, files, local_files, file_hashes, file_sizes, time FROM post_versions WHERE post_id = $1 ORDER BY version DESC",
+			post_id
+		)
+		.fetch_all(db)
+		.await
+		.unwrap_or_default()
+		.into_iter()
+		.map(|version| PostVersion {
+			version: version.version,
+			changelog: version.changelog,
+			files: version.files,
+			local_files: version.local_files,
+			file_hashes: version.file_hashes,
+			file_sizes: version.file_sizes,
+			time: version.time.assume_offset(time::UtcOffset::UTC),
 		})
+		.collect()
 	}
 }
 
@@ -51,7 +51,7 @@ pub struct Urlset {
 #[axum::debug_handler]
 pub async fn sitemap(State(state): State<AppState>) -> Result<(HeaderMap, String), StatusCode> {
 	let mut urls = Vec::new();
-	let latest_date = sqlx::query!("SELECT time FROM posts ORDER BY time")
+	let latest_date = sqlx::query!("SELECT time FROM posts WHERE NOT draft ORDER BY time")
 		.fetch_one(&state.db)
 		.await;
 
@@ -77,7 +77,7 @@ pub async fn sitemap(State(state): State<AppState>) -> Result<(HeaderMap, String
 	};
 	urls.push(base_url);
 
-	let posts = sqlx::query!("SELECT id, time FROM posts ORDER BY time DESC")
+	let posts = sqlx::query!("SELECT id, time FROM posts WHERE NOT draft ORDER BY time DESC")
 		.fetch_all(&state.db)
 		.await;
 	if let Ok(posts) = posts {
@@ -100,12 +100,12 @@ pub async fn sitemap(State(state): State<AppState>) -> Result<(HeaderMap, String
 		}
 	};
 
-	let users = sqlx::query!("SELECT DISTINCT u.id FROM users u LEFT JOIN post_authors pa ON pa.user_id = u.id WHERE pa.post_id IS NOT NULL ORDER BY id")
+	let users = sqlx::query!("SELECT DISTINCT u.id FROM users u LEFT JOIN post_authors pa ON pa.user_id = u.id LEFT JOIN posts p ON p.id = pa.post_id WHERE pa.post_id IS NOT NULL AND NOT p.draft ORDER BY u.id")
 		.fetch_all(&state.db)
 		.await;
 	if let Ok(users) = users {
 		for user in users {
-			let lastmod = sqlx::query!("SELECT p.time FROM post_authors pa LEFT JOIN posts p ON p.id = pa.post_id WHERE pa.user_id = $1 ORDER BY p.time DESC LIMIT 1", user.id).fetch_one(&state.db).await;
+			let lastmod = sqlx::query!("SELECT p.time FROM post_authors pa LEFT JOIN posts p ON p.id = pa.post_id WHERE pa.user_id = $1 AND NOT p.draft ORDER BY p.time DESC LIMIT 1", user.id).fetch_one(&state.db).await;
 			let lastmod = if let Ok(lastmod) = lastmod {
 				Some(Lastmod {
 					lastmod: lastmod.time.date().to_string(),
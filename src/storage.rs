@@ -0,0 +1,147 @@
+use sha2::Digest;
+use std::path::PathBuf;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+#[axum::async_trait]
+pub trait Storage: Send + Sync {
+	/// Path on disk that a stored file lives at, used by anything that needs to read it directly
+	fn local_path(&self, path: &str) -> PathBuf;
+
+	/// Creates (or truncates) a file for writing
+	async fn put(&self, path: &str) -> Option<File> {
+		let local_path = self.local_path(path);
+		if let Some(parent) = local_path.parent() {
+			_ = tokio::fs::create_dir_all(parent).await;
+		}
+		File::create(local_path).await.ok()
+	}
+
+	/// Opens a file for writing at its end, creating it if needed, used to resume uploads
+	async fn append(&self, path: &str) -> Option<File> {
+		let local_path = self.local_path(path);
+		if let Some(parent) = local_path.parent() {
+			_ = tokio::fs::create_dir_all(parent).await;
+		}
+		tokio::fs::OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(local_path)
+			.await
+			.ok()
+	}
+
+	async fn open(&self, path: &str) -> Option<File> {
+		File::open(self.local_path(path)).await.ok()
+	}
+
+	/// Size in bytes of a stored file
+	async fn size(&self, path: &str) -> Option<u64> {
+		let metadata = tokio::fs::metadata(self.local_path(path)).await.ok()?;
+		Some(metadata.len())
+	}
+
+	/// Lowercase hex SHA-256 of a stored file
+	async fn sha256(&self, path: &str) -> Option<String> {
+		let mut file = self.open(path).await?;
+		let mut hasher = sha2::Sha256::new();
+		let mut buf = vec![0; 1024 * 1024];
+		loop {
+			let read = file.read(&mut buf).await.ok()?;
+			if read == 0 {
+				break;
+			}
+			hasher.update(&buf[..read]);
+		}
+		Some(format!("{:x}", hasher.finalize()))
+	}
+
+	async fn delete(&self, path: &str);
+
+	async fn public_link(&self, path: &str) -> Option<String>;
+}
+
+pub struct LocalStorage {
+	pub root: PathBuf,
+}
+
+#[axum::async_trait]
+impl Storage for LocalStorage {
+	fn local_path(&self, path: &str) -> PathBuf {
+		self.root.join(path)
+	}
+
+	async fn delete(&self, path: &str) {
+		_ = tokio::fs::remove_file(self.local_path(path)).await;
+	}
+
+	async fn public_link(&self, path: &str) -> Option<String> {
+		if !self.local_path(path).exists() {
+			return None;
+		}
+
+		let mut url = url::Url::parse("http://localhost/files").ok()?;
+		url.path_segments_mut().ok()?.extend(path.split('/'));
+		Some(String::from(url.path()))
+	}
+}
+
+pub struct PixeldrainStorage {
+	pub mount: PathBuf,
+	pub remote: String,
+	pub rclone_config: String,
+}
+
+impl Default for PixeldrainStorage {
+	fn default() -> Self {
+		Self {
+			mount: PathBuf::from("/pixeldrain"),
+			remote: String::from("pixeldrainfs:/divamodarchive"),
+			rclone_config: String::from("/etc/rclone-mnt.conf"),
+		}
+	}
+}
+
+#[axum::async_trait]
+impl Storage for PixeldrainStorage {
+	fn local_path(&self, path: &str) -> PathBuf {
+		self.mount.join(path)
+	}
+
+	async fn delete(&self, path: &str) {
+		_ = tokio::process::Command::new("rclone")
+			.arg("delete")
+			.arg(format!("{}/{}", self.remote, path))
+			.arg(format!("--config={}", self.rclone_config))
+			.output()
+			.await;
+	}
+
+	async fn public_link(&self, path: &str) -> Option<String> {
+		let command = tokio::process::Command::new("rclone")
+			.arg("link")
+			.arg(format!("{}/{}", self.remote, path))
+			.arg(format!("--config={}", self.rclone_config))
+			.output()
+			.await;
+		let Ok(command) = command else {
+			return None;
+		};
+		if !command.status.success() {
+			return None;
+		}
+		let Ok(path) = String::from_utf8(command.stdout) else {
+			return None;
+		};
+
+		if !path.starts_with("https://pixeldrain.com/d/") {
+			return None;
+		}
+
+		let download = path.trim().replace(
+			"https://pixeldrain.com/d/",
+			"https://pixeldrain.com/api/filesystem/",
+		);
+		Some(format!("{download}?download"))
+	}
+}
@@ -31,7 +31,7 @@ pub fn route(state: AppState) -> Router {
 		.route("/cstm_items", get(cstm_items))
 		.route("/pv_spreadsheet", get(pv_spreadsheet))
 		.route("/reserve", get(reserve))
-		//.route("/admin", get(admin))
+		.route("/admin", get(admin))
 		.with_state(state)
 }
 
@@ -191,7 +191,7 @@ async fn liked(
 		SELECT p.id
 		FROM liked_posts lp
 		LEFT JOIN posts p ON lp.post_id = p.id
-		WHERE lp.user_id = $1
+		WHERE lp.user_id = $1 AND NOT p.draft
 		ORDER by p.time DESC
 		"#,
 		id,
@@ -256,7 +256,9 @@ async fn user(
 	let mut posts = Vec::new();
 	for post in user_posts {
 		if let Some(post) = Post::get_short(post.id, &state.db).await {
-			posts.push(post);
+			if post.is_visible_to(base.user.as_ref(), &state.config) {
+				posts.push(post);
+			}
 		}
 	}
 
@@ -472,6 +474,12 @@ struct PostTemplate {
 	conflicting_pv_reservations: BTreeMap<User, Vec<i32>>,
 	conflicting_module_reservations: BTreeMap<User, Vec<i32>>,
 	conflicting_cstm_item_reservations: BTreeMap<User, Vec<i32>>,
+	extraction: Option<crate::jobs::ExtractionJob>,
+	/// Versions before the current one, newest first
+	older_versions: Vec<PostVersion>,
+	changelogs: Vec<Changelog>,
+	/// Name and counted downloads of each file, only filled in for authors
+	file_downloads: Vec<(String, i64)>,
 }
 
 async fn post_redirect(Path(id): Path<i32>) -> Redirect {
@@ -490,6 +498,12 @@ async fn post_detail(
 			status: StatusCode::NOT_FOUND,
 		});
 	};
+	if !post.is_visible_to(user.as_ref(), &state.config) {
+		return Err(ErrorTemplate {
+			base,
+			status: StatusCode::NOT_FOUND,
+		});
+	}
 
 	let has_liked = if let Some(user) = &user {
 		let Ok(has_liked) = sqlx::query!(
@@ -517,38 +531,38 @@ async fn post_detail(
 		false
 	};
 
-	let Json(pvs) = search_pvs(
-		axum_extra::extract::Query(SearchParams {
-			query: None,
-			filter: Some(format!("post={}", post.id)),
+	let pvs = find_pvs(
+		PvSearchParams {
+			post: Some(post.id),
 			limit: Some(2000),
 			offset: Some(0),
-		}),
-		State(state.clone()),
+			..Default::default()
+		},
+		&state,
 	)
 	.await
 	.unwrap_or_default();
 
-	let Json(modules) = crate::api::ids::search_modules(
-		axum_extra::extract::Query(SearchParams {
-			query: None,
-			filter: Some(format!("post_id={}", post.id)),
+	let modules = find_modules(
+		SearchParams {
+			post: Some(post.id),
 			limit: Some(2000),
 			offset: Some(0),
-		}),
-		State(state.clone()),
+			..Default::default()
+		},
+		&state,
 	)
 	.await
 	.unwrap_or_default();
 
-	let Json(cstm_items) = crate::api::ids::search_cstm_items(
-		axum_extra::extract::Query(SearchParams {
-			query: None,
-			filter: Some(format!("post_id={}", post.id)),
+	let cstm_items = find_cstm_items(
+		SearchParams {
+			post: Some(post.id),
 			limit: Some(2000),
 			offset: Some(0),
-		}),
-		State(state.clone()),
+			..Default::default()
+		},
+		&state,
 	)
 	.await
 	.unwrap_or_default();
@@ -567,14 +581,14 @@ async fn post_detail(
 			.intersperse(String::from(" OR "))
 			.collect::<String>();
 
-		let Json(conflicting_pvs) = search_pvs(
-			axum_extra::extract::Query(SearchParams {
-				query: None,
+		let conflicting_pvs = find_pvs(
+			PvSearchParams {
 				filter: Some(filter),
 				limit: Some(2000),
 				offset: Some(0),
-			}),
-			State(state.clone()),
+				..Default::default()
+			},
+			&state,
 		)
 		.await
 		.unwrap_or_default();
@@ -598,14 +612,14 @@ async fn post_detail(
 			.intersperse(String::from(" OR "))
 			.collect::<String>();
 
-		let Json(conflicting_modules) = crate::api::ids::search_modules(
-			axum_extra::extract::Query(SearchParams {
-				query: None,
+		let conflicting_modules = find_modules(
+			SearchParams {
 				filter: Some(filter),
 				limit: Some(2000),
 				offset: Some(0),
-			}),
-			State(state.clone()),
+				..Default::default()
+			},
+			&state,
 		)
 		.await
 		.unwrap_or_default();
@@ -629,14 +643,14 @@ async fn post_detail(
 			.intersperse(String::from(" OR "))
 			.collect::<String>();
 
-		let Json(conflicting_cstm_items) = crate::api::ids::search_cstm_items(
-			axum_extra::extract::Query(SearchParams {
-				query: None,
+		let conflicting_cstm_items = find_cstm_items(
+			SearchParams {
 				filter: Some(filter),
 				limit: Some(2000),
 				offset: Some(0),
-			}),
-			State(state.clone()),
+				..Default::default()
+			},
+			&state,
 		)
 		.await
 		.unwrap_or_default();
@@ -760,6 +774,44 @@ async fn post_detail(
 		}
 	}
 
+	let extraction = if is_author || user.as_ref().is_some_and(|u| u.is_admin(&state.config)) {
+		crate::jobs::ExtractionJob::get(post.id, &state.db).await
+	} else {
+		None
+	};
+
+	let file_downloads = if is_author {
+		let counts = crate::api::posts::file_downloads(post.id, &state.db).await;
+		post.local_files
+			.iter()
+			.enumerate()
+			.map(|(i, file)| {
+				let name = file.split('/').last().unwrap_or(file);
+				let count = counts
+					.iter()
+					.find(|count| count.variant as usize == i)
+					.map_or(0, |count| count.count);
+				(String::from(name), count)
+			})
+			.collect()
+	} else {
+		Vec::new()
+	};
+
+	let changelogs = Changelog::list(post.id, &state.db).await;
+	let mut older_versions = PostVersion::list(post.id, &state.db)
+		.await
+		.into_iter()
+		.skip(1)
+		.collect::<Vec<_>>();
+	for version in &mut older_versions {
+		for file in &mut version.local_files {
+			if let Some(name) = file.split('/').last() {
+				*file = String::from(name);
+			}
+		}
+	}
+
 	Ok(PostTemplate {
 		user,
 		jwt: base.jwt.clone(),
@@ -782,6 +834,10 @@ async fn post_detail(
 		conflicting_pv_reservations,
 		conflicting_module_reservations,
 		conflicting_cstm_item_reservations,
+		extraction,
+		older_versions,
+		changelogs,
+		file_downloads,
 	})
 }
 
@@ -798,9 +854,20 @@ async fn search(
 	base: BaseTemplate,
 	State(state): State<AppState>,
 ) -> Result<SearchTemplate, ErrorTemplate> {
-	if query.query.is_some() || query.sort.is_some() || query.filter.is_some() {
+	if query.query.is_some()
+		|| query.sort.is_some()
+		|| query.filter.is_some()
+		|| query.post_type.is_some()
+		|| query.author.is_some()
+		|| query.after.is_some()
+		|| query.before.is_some()
+		|| !query.tag.is_empty()
+		|| query.game.is_some()
+		|| query.dml_version.is_some()
+	{
 		let Json(posts) = crate::api::posts::search_posts(
 			axum_extra::extract::Query(query.clone()),
+			base.user.clone(),
 			State(state.clone()),
 		)
 		.await
@@ -820,6 +887,7 @@ async fn search(
 		r#"
 		SELECT id
 		FROM posts
+		WHERE NOT draft
 		ORDER BY time DESC
 		LIMIT 20
 		"#
@@ -866,7 +934,7 @@ struct ReportTemplate {
 async fn report(
 	Path(id): Path<i32>,
 	base: BaseTemplate,
-	_: User,
+	user: User,
 	State(state): State<AppState>,
 ) -> Result<ReportTemplate, ErrorTemplate> {
 	let Some(post) = Post::get_short(id, &state.db).await else {
@@ -875,10 +943,77 @@ async fn report(
 			status: StatusCode::NOT_FOUND,
 		});
 	};
+	if !post.is_visible_to(Some(&user), &state.config) {
+		return Err(ErrorTemplate {
+			base,
+			status: StatusCode::NOT_FOUND,
+		});
+	}
 
 	Ok(ReportTemplate { base, post })
 }
 
+struct Report {
+	id: i32,
+	post_id: i32,
+	post_name: String,
+	/// None for automatic reports, such as duplicate files found on upload
+	reporter: Option<(i64, String)>,
+	text: String,
+	time: time::PrimitiveDateTime,
+}
+
+#[derive(Template)]
+#[template(path = "admin.html")]
+struct AdminTemplate {
+	base: BaseTemplate,
+	reports: Vec<Report>,
+}
+
+async fn admin(
+	base: BaseTemplate,
+	user: User,
+	State(state): State<AppState>,
+) -> Result<AdminTemplate, ErrorTemplate> {
+	if !user.is_admin(&state.config) {
+		return Err(ErrorTemplate {
+			base,
+			status: StatusCode::UNAUTHORIZED,
+		});
+	}
+
+	let reports = sqlx::query!(
+		r#"
+		SELECT r.id, r.post_id, p.name AS post_name, r.text, r.time, u.id AS "user_id?", u.display_name AS "display_name?"
+		FROM reports r
+		JOIN posts p ON r.post_id = p.id
+		LEFT JOIN users u ON r.user_id = u.id
+		WHERE r.admin_handled IS NULL
+		ORDER BY r.time DESC
+		"#
+	)
+	.fetch_all(&state.db)
+	.await
+	.map_err(|_| ErrorTemplate {
+		base: base.clone(),
+		status: StatusCode::INTERNAL_SERVER_ERROR,
+	})?
+	.into_iter()
+	.map(|report| Report {
+		id: report.id,
+		post_id: report.post_id,
+		post_name: report.post_name,
+		reporter: report
+			.user_id
+			.map(|id| (id, report.display_name.unwrap_or_default())),
+		text: report.text,
+		time: report.time,
+	})
+	.collect();
+
+	Ok(AdminTemplate { base, reports })
+}
+
 #[derive(Template)]
 #[template(path = "pvs.html")]
 struct PvsTemplate {
@@ -887,14 +1022,13 @@ struct PvsTemplate {
 }
 
 async fn pvs(base: BaseTemplate, State(state): State<AppState>) -> PvsTemplate {
-	let Json(pvs) = crate::api::ids::search_pvs(
-		axum_extra::extract::Query(SearchParams {
-			query: None,
-			filter: None,
+	let pvs = find_pvs(
+		PvSearchParams {
 			limit: Some(20),
 			offset: Some(0),
-		}),
-		State(state),
+			..Default::default()
+		},
+		&state,
 	)
 	.await
 	.unwrap_or_default();
@@ -910,14 +1044,13 @@ struct ModulesTemplate {
 }
 
 async fn modules(base: BaseTemplate, State(state): State<AppState>) -> ModulesTemplate {
-	let Json(modules) = crate::api::ids::search_modules(
-		axum_extra::extract::Query(SearchParams {
-			query: None,
-			filter: None,
+	let modules = find_modules(
+		SearchParams {
 			limit: Some(20),
 			offset: Some(0),
-		}),
-		State(state),
+			..Default::default()
+		},
+		&state,
 	)
 	.await
 	.unwrap_or_default();
@@ -933,14 +1066,13 @@ struct CstmItemsTemplate {
 }
 
 async fn cstm_items(base: BaseTemplate, State(state): State<AppState>) -> CstmItemsTemplate {
-	let Json(cstm_items) = crate::api::ids::search_cstm_items(
-		axum_extra::extract::Query(SearchParams {
-			query: None,
-			filter: None,
+	let cstm_items = find_cstm_items(
+		SearchParams {
 			limit: Some(20),
 			offset: Some(0),
-		}),
-		State(state),
+			..Default::default()
+		},
+		&state,
 	)
 	.await
 	.unwrap_or_default();
@@ -987,14 +1119,13 @@ async fn pv_spreadsheet(base: BaseTemplate, State(state): State<AppState>) -> Pv
 	})
 	.collect::<BTreeMap<_, _>>();
 
-	let Json(search) = search_pvs(
-		axum_extra::extract::Query(SearchParams {
-			query: None,
-			filter: None,
+	let search = find_pvs(
+		PvSearchParams {
 			limit: Some(100_000),
 			offset: Some(0),
-		}),
-		State(state.clone()),
+			..Default::default()
+		},
+		&state,
 	)
 	.await
 	.unwrap_or_default();